@@ -1,3 +1,5 @@
+DROP TABLE IF EXISTS pseudonyms;
+DROP TABLE IF EXISTS population_samples;
 DROP TABLE IF EXISTS player_events;
 DROP TABLE IF EXISTS server_events;
 DROP TABLE IF EXISTS server_settings;
@@ -13,7 +13,7 @@ CREATE TABLE server_settings (
     has_password BOOLEAN NOT NULL,
     game_version TEXT NOT NULL,
     bots INTEGER NOT NULL,
-    created_at DATETIME NOT NULL
+    created_at INTEGER NOT NULL
 );
 
 CREATE TABLE players (
@@ -27,8 +27,8 @@ CREATE TABLE sessions (
     player_id INTEGER NOT NULL REFERENCES players(player_id),
     score INTEGER NOT NULL,
     duration REAL NOT NULL,
-    joined_at DATETIME NOT NULL,
-    left_at DATETIME NOT NULL
+    joined_at INTEGER NOT NULL,
+    left_at INTEGER NOT NULL
 );
 
 CREATE TABLE server_events (
@@ -36,7 +36,7 @@ CREATE TABLE server_events (
     server_id INTEGER NOT NULL REFERENCES servers(server_id),
     event_type TEXT NOT NULL,
     event_data TEXT,
-    created_at DATETIME NOT NULL
+    created_at INTEGER NOT NULL
 );
 
 CREATE TABLE player_events (
@@ -45,5 +45,28 @@ CREATE TABLE player_events (
     player_id INTEGER NOT NULL REFERENCES players(player_id),
     event_type TEXT NOT NULL,
     event_data TEXT,
-    created_at DATETIME NOT NULL
+    created_at INTEGER NOT NULL
 );
+
+CREATE TABLE population_samples (
+    sample_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    server_id INTEGER NOT NULL REFERENCES servers(server_id),
+    players INTEGER NOT NULL,
+    bots INTEGER NOT NULL,
+    players_joined INTEGER NOT NULL,
+    players_left INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE pseudonyms (
+    player_id INTEGER PRIMARY KEY REFERENCES players(player_id) ON DELETE CASCADE
+);
+
+
+CREATE INDEX server_settings_server_created ON server_settings(server_id, created_at);
+CREATE INDEX sessions_server_joined ON sessions(server_id, joined_at);
+CREATE INDEX sessions_player ON sessions(player_id);
+CREATE INDEX server_events_server_created ON server_events(server_id, created_at);
+CREATE INDEX player_events_player_created ON player_events(player_id, created_at);
+CREATE INDEX player_events_server_created ON player_events(server_id, created_at);
+CREATE INDEX population_samples_server_created ON population_samples(server_id, created_at);
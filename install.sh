@@ -3,6 +3,7 @@
 # Set variables
 SCAN_EXEC_PATH=/usr/local/bin/tf2-scan
 ANALYSIS_EXEC_PATH=/usr/local/bin/tf2-analysis
+API_EXEC_PATH=/usr/local/bin/tf2-api
 CONFIG_DIR=/etc/tf2-surveillance
 DB_DIR=/var/lib/tf2-surveillance
 DB_FILE=players.db
@@ -21,6 +22,7 @@ sudo rm $SCAN_EXEC_PATH
 # Copy Binaries
 sudo cp target/release/tf2-scan $SCAN_EXEC_PATH
 sudo cp target/release/tf2-analysis $ANALYSIS_EXEC_PATH
+sudo cp target/release/tf2-api $API_EXEC_PATH
 sudo chmod +x $SCAN_EXEC_PATH
 sudo chmod +x $ANALYSIS_EXEC_PATH
 
@@ -46,6 +48,9 @@ fi
 if [ -f "$DB_DIR/$DB_FILE" ]; then
     read -p "Database file already exists. Do you want to overwrite it? (y/n): " OVERWRITE_DB
     if [[ "$OVERWRITE_DB" == "y" || "$OVERWRITE_DB" == "Y" ]]; then
+        # Keep a copy of the old database before replacing it, db backup reads it without migrating so the copy keeps the old schema
+        BACKUP_FILE=$DB_DIR/$DB_FILE.$(date +%Y%m%d%H%M%S).bak
+        sudo $SCAN_EXEC_PATH -c $CONFIG_DIR/config.toml -d $DB_DIR/$DB_FILE db backup $BACKUP_FILE || exit 1
         # Copy database
         sudo cp db-tools/players-empty.db $DB_DIR/$DB_FILE
     else
@@ -70,7 +75,7 @@ Description=TF2-Surveillance
 After=network.target
 
 [Service]
-ExecStart=$SCAN_EXEC_PATH -c $CONFIG_DIR/config.toml
+ExecStart=$SCAN_EXEC_PATH -c $CONFIG_DIR/config.toml run
 Restart=always
 StandardOutput=syslog
 StandardError=syslog
@@ -0,0 +1,358 @@
+use crate::html::escape;
+use chrono::NaiveDateTime;
+use resvg::{tiny_skia, usvg};
+use std::{fmt::Write, fs, path::Path};
+
+const FONT: &str = "font-family=\"sans-serif\" font-size=\"12\"";
+pub const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+//Categorical colours for series and bars
+const SERIES: [&str; 8] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+//Viridis colour stops, low to high
+const PALETTE: [(u8, u8, u8); 5] = [(68, 1, 84), (59, 82, 139), (33, 145, 140), (94, 201, 98), (253, 231, 37)];
+
+//One line per series over a shared time axis
+pub struct LineChart {
+    pub title: String,
+    pub unit: String,
+    pub series: Vec<(String, Vec<(NaiveDateTime, f64)>)>,
+}
+
+//Labelled horizontal bars
+pub struct BarChart {
+    pub title: String,
+    pub unit: String,
+    pub bars: Vec<(String, f64)>,
+}
+
+//Labelled vertical columns, used for histograms
+pub struct ColumnChart {
+    pub title: String,
+    pub unit: String,
+    pub columns: Vec<(String, f64)>,
+}
+
+//One row per server, green while monitored with red spans for each outage
+pub struct Timeline {
+    pub title: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub rows: Vec<TimelineRow>,
+}
+
+pub struct TimelineRow {
+    pub label: String,
+    pub monitored: (NaiveDateTime, NaiveDateTime),
+    pub outages: Vec<(NaiveDateTime, NaiveDateTime)>,
+}
+
+//One 7 x 24 grid of values per server, weekday rows and hour columns
+pub struct Heatmap {
+    pub title: String,
+    pub unit: String,
+    pub servers: Vec<(String, [[f64; 24]; 7])>,
+}
+
+pub fn heatmap_svg(heatmap: &Heatmap) -> String {
+    let cell = 24.0;
+    let left = 48.0;
+    let top = 40.0;
+    let block = cell * 7.0 + 44.0;
+    let width = left + cell * 24.0 + 90.0;
+    let height = top + block * heatmap.servers.len() as f64 + 10.0;
+
+    let max = heatmap
+        .servers
+        .iter()
+        .flat_map(|(_, grid)| grid.iter().flatten())
+        .cloned()
+        .fold(0.0, f64::max);
+
+    let mut svg = header(width, height);
+    title(&mut svg, left, &heatmap.title);
+
+    for (index, (server, grid)) in heatmap.servers.iter().enumerate() {
+        let y = top + block * index as f64;
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {} font-weight=\"bold\">{}</text>", left, y + 14.0, FONT, escape(server));
+
+        for hour in (0..24).step_by(3) {
+            let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {} text-anchor=\"middle\">{:02}</text>", left + cell * (hour as f64 + 0.5), y + 34.0, FONT, hour);
+        }
+
+        for (weekday, hours) in grid.iter().enumerate() {
+            let row = y + 40.0 + cell * weekday as f64;
+            let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {} text-anchor=\"end\">{}</text>", left - 6.0, row + cell * 0.7, FONT, WEEKDAYS[weekday]);
+            for (hour, value) in hours.iter().enumerate() {
+                let _ = writeln!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"><title>{} {:02}:00 {:.1} {}</title></rect>",
+                    left + cell * hour as f64, row, cell - 1.0, cell - 1.0, color(*value, max), WEEKDAYS[weekday], hour, value, escape(&heatmap.unit)
+                );
+            }
+        }
+    }
+
+    legend(&mut svg, left + cell * 24.0 + 16.0, top + 40.0, max, &heatmap.unit);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn line_svg(chart: &LineChart) -> String {
+    let (width, height) = (900.0, 420.0);
+    let plot = Plot { left: 60.0, top: 40.0, width: width - 60.0 - 170.0, height: height - 40.0 - 50.0 };
+
+    let times: Vec<i64> = chart.series.iter().flat_map(|(_, points)| points.iter().map(|(time, _)| timestamp(*time))).collect();
+    let (start, end) = match (times.iter().min(), times.iter().max()) {
+        (Some(start), Some(end)) if end > start => (*start, *end),
+        (Some(start), _) => (*start, start + 1),
+        _ => (0, 1),
+    };
+    let max = nice_max(chart.series.iter().flat_map(|(_, points)| points.iter().map(|(_, value)| *value)).fold(0.0, f64::max));
+
+    let mut svg = header(width, height);
+    title(&mut svg, plot.left, &chart.title);
+    y_axis(&mut svg, &plot, max, &chart.unit);
+    time_axis(&mut svg, &plot, start, end);
+
+    for (index, (label, points)) in chart.series.iter().enumerate() {
+        let colour = SERIES[index % SERIES.len()];
+        let path: Vec<String> = points
+            .iter()
+            .map(|(time, value)| {
+                let x = plot.left + (timestamp(*time) - start) as f64 / (end - start) as f64 * plot.width;
+                let y = plot.top + plot.height - value / max * plot.height;
+                format!("{:.1},{:.1}", x, y)
+            })
+            .collect();
+        let _ = writeln!(svg, "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>", colour, path.join(" "));
+
+        let y = plot.top + 10.0 + 18.0 * index as f64;
+        let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{}\"/>", width - 160.0, y - 10.0, colour);
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {}>{}</text>", width - 142.0, y, FONT, escape(label));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn bar_svg(chart: &BarChart) -> String {
+    let label_width = 8.0 * chart.bars.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0) as f64 + 16.0;
+    let bar = 22.0;
+    let width = 900.0;
+    let plot = Plot { left: label_width, top: 40.0, width: width - label_width - 80.0, height: bar * chart.bars.len() as f64 };
+    let max = nice_max(chart.bars.iter().map(|(_, value)| *value).fold(0.0, f64::max));
+
+    let mut svg = header(width, plot.top + plot.height + 50.0);
+    title(&mut svg, plot.left, &chart.title);
+
+    for (index, (label, value)) in chart.bars.iter().enumerate() {
+        let y = plot.top + bar * index as f64;
+        let length = value / max * plot.width;
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {} text-anchor=\"end\">{}</text>", plot.left - 6.0, y + bar * 0.65, FONT, escape(label));
+        let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"/>", plot.left, y + 2.0, length, bar - 4.0, SERIES[0]);
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" {}>{:.1}</text>", plot.left + length + 4.0, y + bar * 0.65, FONT, value);
+    }
+
+    let axis = plot.top + plot.height;
+    let _ = writeln!(svg, "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"black\"/>", plot.left, axis, plot.left + plot.width);
+    for tick in 0..=4 {
+        let x = plot.left + plot.width * tick as f64 / 4.0;
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" {} text-anchor=\"middle\">{}</text>", x, axis + 16.0, FONT, format_number(max * tick as f64 / 4.0));
+    }
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {} text-anchor=\"middle\">{}</text>", plot.left + plot.width / 2.0, axis + 36.0, FONT, escape(&chart.unit));
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn column_svg(chart: &ColumnChart) -> String {
+    let (width, height) = (900.0, 420.0);
+    let plot = Plot { left: 60.0, top: 40.0, width: width - 80.0, height: height - 40.0 - 60.0 };
+    let max = nice_max(chart.columns.iter().map(|(_, value)| *value).fold(0.0, f64::max));
+    let column = plot.width / chart.columns.len().max(1) as f64;
+
+    let mut svg = header(width, height);
+    title(&mut svg, plot.left, &chart.title);
+    y_axis(&mut svg, &plot, max, &chart.unit);
+
+    //Only label every nth column so the labels don't overlap
+    let every = (chart.columns.len() as f64 / 12.0).ceil().max(1.0) as usize;
+    for (index, (label, value)) in chart.columns.iter().enumerate() {
+        let x = plot.left + column * index as f64;
+        let length = value / max * plot.height;
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>{} : {}</title></rect>",
+            x + 1.0, plot.top + plot.height - length, (column - 2.0).max(1.0), length, SERIES[0], escape(label), value
+        );
+        if index % every == 0 {
+            let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" {} text-anchor=\"middle\">{}</text>", x + column / 2.0, plot.top + plot.height + 16.0, FONT, escape(label));
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn timeline_svg(chart: &Timeline) -> String {
+    let label_width = 8.0 * chart.rows.iter().map(|row| row.label.chars().count()).max().unwrap_or(0) as f64 + 16.0;
+    let row_height = 26.0;
+    let width = 900.0;
+    let plot = Plot { left: label_width, top: 40.0, width: width - label_width - 30.0, height: row_height * chart.rows.len() as f64 };
+    let (start, end) = (timestamp(chart.start), timestamp(chart.end).max(timestamp(chart.start) + 1));
+    let x = |time: NaiveDateTime| plot.left + (timestamp(time).clamp(start, end) - start) as f64 / (end - start) as f64 * plot.width;
+
+    let mut svg = header(width, plot.top + plot.height + 40.0);
+    title(&mut svg, plot.left, &chart.title);
+    time_axis(&mut svg, &plot, start, end);
+
+    for (index, row) in chart.rows.iter().enumerate() {
+        let y = plot.top + row_height * index as f64;
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {} text-anchor=\"end\">{}</text>", plot.left - 6.0, y + row_height * 0.65, FONT, escape(&row.label));
+        let (from, to) = (x(row.monitored.0), x(row.monitored.1));
+        let _ = writeln!(svg, "<rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#2ca02c\"/>", from, y + 4.0, to - from, row_height - 8.0);
+        for (outage_start, outage_end) in &row.outages {
+            let (from, to) = (x(*outage_start), x(*outage_end));
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#d62728\"><title>{} - {}</title></rect>",
+                from, y + 4.0, (to - from).max(1.0), row_height - 8.0, outage_start.format("%Y-%m-%d %H:%M"), outage_end.format("%H:%M")
+            );
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+//Write the svg as is, or rasterise it when the path ends in .png
+pub fn save(svg: &str, path: &str) -> Result<(), String> {
+    match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some("png") => {
+            let mut options = usvg::Options::default();
+            let fonts = options.fontdb_mut();
+            fonts.load_system_fonts();
+
+            //The default sans-serif family is Arial, which most headless linux boxes do not have
+            let sans_serif = usvg::fontdb::Query { families: &[usvg::fontdb::Family::SansSerif], ..Default::default() };
+            if fonts.query(&sans_serif).is_none() {
+                let fallback = ["DejaVu Sans", "Liberation Sans", "Noto Sans"]
+                    .iter()
+                    .map(|family| family.to_string())
+                    .find(|family| fonts.faces().any(|face| face.families.iter().any(|(name, _)| name == family)))
+                    .or_else(|| fonts.faces().next().and_then(|face| face.families.first()).map(|(name, _)| name.clone()));
+                if let Some(family) = fallback {
+                    fonts.set_sans_serif_family(family);
+                }
+            }
+
+            let tree = usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+            let size = tree.size().to_int_size();
+            let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).ok_or("Chart has no size")?;
+            resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+            pixmap.save_png(path).map_err(|e| e.to_string())
+        }
+        Some("svg") => fs::write(path, svg).map_err(|e| e.to_string()),
+        _ => Err(format!("Unknown chart format ({}), expected .svg or .png", path)),
+    }
+}
+
+struct Plot {
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+}
+
+fn title(svg: &mut String, x: f64, title: &str) {
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"22\" font-family=\"sans-serif\" font-size=\"16\">{}</text>", x, escape(title));
+}
+
+fn y_axis(svg: &mut String, plot: &Plot, max: f64, unit: &str) {
+    let bottom = plot.top + plot.height;
+    let _ = writeln!(svg, "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"black\"/>", plot.left, plot.top, bottom);
+    let _ = writeln!(svg, "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"black\"/>", plot.left, bottom, plot.left + plot.width);
+    for tick in 0..=4 {
+        let y = bottom - plot.height * tick as f64 / 4.0;
+        let _ = writeln!(svg, "<line x1=\"{0}\" y1=\"{1:.1}\" x2=\"{2}\" y2=\"{1:.1}\" stroke=\"#dddddd\"/>", plot.left + 1.0, y, plot.left + plot.width);
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{:.1}\" {} text-anchor=\"end\">{}</text>", plot.left - 6.0, y + 4.0, FONT, format_number(max * tick as f64 / 4.0));
+    }
+    let _ = writeln!(
+        svg,
+        "<text x=\"14\" y=\"{0:.1}\" {1} text-anchor=\"middle\" transform=\"rotate(-90 14 {0:.1})\">{2}</text>",
+        plot.top + plot.height / 2.0, FONT, escape(unit)
+    );
+}
+
+//Ticks at a whole number of hours or days depending on the range
+fn time_axis(svg: &mut String, plot: &Plot, start: i64, end: i64) {
+    let range = end - start;
+    let step = [3600, 3 * 3600, 6 * 3600, 12 * 3600, 86400, 2 * 86400, 7 * 86400, 14 * 86400, 28 * 86400]
+        .into_iter()
+        .find(|step| range / step <= 8)
+        .unwrap_or(range.max(1));
+    let format = if step < 86400 { "%m-%d %H:%M" } else { "%Y-%m-%d" };
+    let bottom = plot.top + plot.height;
+
+    let mut tick = start - start.rem_euclid(step) + step;
+    while tick <= end {
+        let x = plot.left + (tick - start) as f64 / range.max(1) as f64 * plot.width;
+        let label = chrono::DateTime::from_timestamp(tick, 0).unwrap().naive_utc().format(format).to_string();
+        let _ = writeln!(svg, "<line x1=\"{0:.1}\" y1=\"{1}\" x2=\"{0:.1}\" y2=\"{2}\" stroke=\"black\"/>", x, bottom, bottom + 4.0);
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" {} text-anchor=\"middle\">{}</text>", x, bottom + 18.0, FONT, label);
+        tick += step;
+    }
+}
+
+//Round the top of an axis up to 1, 2 or 5 times a power of ten
+fn nice_max(max: f64) -> f64 {
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(max.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter().map(|step| step * magnitude).find(|nice| *nice >= max).unwrap()
+}
+
+fn format_number(value: f64) -> String {
+    match value.fract() == 0.0 {
+        true => format!("{}", value),
+        false => format!("{:.1}", value),
+    }
+}
+
+fn timestamp(time: NaiveDateTime) -> i64 {
+    time.and_utc().timestamp()
+}
+
+//Vertical colour scale from 0 to max
+fn legend(svg: &mut String, x: f64, y: f64, max: f64, unit: &str) {
+    let steps = 10;
+    let height = 16.0;
+    for step in 0..steps {
+        let value = max * (steps - 1 - step) as f64 / (steps - 1) as f64;
+        let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"14\" height=\"{}\" fill=\"{}\"/>", x, y + height * step as f64, height, color(value, max));
+    }
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {}>{:.1}</text>", x + 18.0, y + 11.0, FONT, max);
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {}>0</text>", x + 18.0, y + height * steps as f64 - 4.0, FONT);
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" {}>{}</text>", x, y + height * steps as f64 + 16.0, FONT, escape(unit));
+}
+
+fn color(value: f64, max: f64) -> String {
+    let position = match max > 0.0 {
+        true => (value / max).clamp(0.0, 1.0) * (PALETTE.len() - 1) as f64,
+        false => 0.0,
+    };
+    let index = (position.floor() as usize).min(PALETTE.len() - 2);
+    let fraction = position - index as f64;
+    let (from, to) = (PALETTE[index], PALETTE[index + 1]);
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * fraction).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+fn header(width: f64, height: f64) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n",
+        width, height
+    )
+}
@@ -0,0 +1,398 @@
+use crate::{database_backend, format_duration, heartbeat, new_a2s_client, pseudonym, retention, scores, sql, storage::{Backend, Storage}, try_read_lines, Config};
+use rusqlite::Connection;
+use std::{fs, net::SocketAddr, process::exit, sync::Mutex, time::Instant};
+
+//Validate the config and server list, then query every server once
+pub fn check_config(config: &Config, db_file: &str, server_file: &str, target_file: &str) {
+    let mut problems = 0;
+
+    let mut problem = |message: String| {
+        println!("  [FAIL] {}", message);
+        problems += 1;
+    };
+
+    println!("Config");
+    if config.refresh_delay == 0 {
+        problem("refresh_delay is 0, servers will be queried back to back".to_string());
+    }
+    if config.webhook_enabled && !config.webhook_url.starts_with("http") {
+        problem(format!("webhook_enabled but webhook_url is not a url ({})", config.webhook_url));
+    }
+    if config.heartbeat_enabled && !config.heartbeat_url.starts_with("http") {
+        problem(format!("heartbeat_enabled but heartbeat_url is not a url ({})", config.heartbeat_url));
+    }
+    if config.heartbeat_enabled && !heartbeat::has_placeholders(&config.heartbeat_url) {
+        println!("  [WARN] heartbeat_url has no placeholders, the scan time is appended to it as in older versions, add {{scan_ms}} where it belongs");
+    }
+    if config.heartbeat_failure_threshold > 0 && config.heartbeat_down_url.is_none() {
+        println!("  [WARN] heartbeat_failure_threshold set without heartbeat_down_url, failing scans will skip the heartbeat");
+    }
+    if config.metrics_enabled && config.metrics_address.parse::<SocketAddr>().is_err() {
+        problem(format!("metrics_address is not a socket address ({})", config.metrics_address));
+    }
+    if config.status_enabled && config.status_address.parse::<SocketAddr>().is_err() {
+        problem(format!("status_address is not a socket address ({})", config.status_address));
+    }
+    if config.metrics_enabled && config.status_enabled && config.metrics_address == config.status_address {
+        problem(format!("metrics_address and status_address are the same ({})", config.status_address));
+    }
+    match retention::Policy::from_config(config) {
+        Ok(policy) if policy.is_empty() => println!("  [ OK ] No retention windows, data is kept forever"),
+        Ok(policy) => println!("  [ OK ] Retention for {} tables, pruned every {}", policy.windows.len(), config.retention_interval),
+        Err(e) => problem(e),
+    }
+    match scores::Timeline::from_config(config) {
+        Ok(timeline) => println!("  [ OK ] {}", timeline.describe()),
+        Err(e) => problem(e),
+    }
+
+    if config.pseudonym_secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+        println!("  [WARN] pseudonym_secret is shorter than 16 characters, names could be recovered by guessing it");
+    }
+
+    match database_backend(config, db_file) {
+        Ok(Backend::Sqlite(..)) => {
+            println!("Database ({})", db_file);
+            match Connection::open_with_flags(db_file, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY) {
+                Ok(connection) => match sql::get_database_stats(&connection) {
+                    Ok(stats) => {
+                        println!("  [ OK ] {} tables", stats.tables.len());
+                        if let Ok((version, latest)) = sql::schema_version(&connection) {
+                            if version < latest {
+                                println!("  [WARN] Schema version {} is behind {}, the scanner migrates it on start", version, latest);
+                            }
+                            if version <= sql::LOCAL_TIMES && config.legacy_time_zone.is_none() && sql::has_local_times(&connection).unwrap_or(true) {
+                                println!("  [WARN] Times are local time strings, set legacy_time_zone to the zone they were written in or the migration fails");
+                            }
+                        }
+                        let plain = sql::get_plain_players(&connection).map(|players| players.len()).unwrap_or(0);
+                        if config.pseudonym_secret.is_some() && plain > 0 {
+                            println!("  [WARN] {} player names are stored in plain text, run db pseudonymise to replace them (with --keep-hashed if older versions already stored pseudonyms)", plain);
+                        }
+                    }
+                    Err(e) => problem(format!("Database is missing the expected tables ({})", e)),
+                },
+                Err(e) => problem(format!("Failed to open database ({})", e)),
+            }
+        }
+        Ok(backend) => {
+            println!("Database ({})", backend);
+            match backend.open() {
+                Ok(mut storage) => match storage.schema_version() {
+                    Ok((0, _)) => println!("  [WARN] Database has no tables yet, the scanner creates them on start"),
+                    Ok((version, latest)) => {
+                        match storage.stats() {
+                            Ok(stats) => println!("  [ OK ] {} tables", stats.tables.len()),
+                            Err(e) => problem(format!("Database is missing the expected tables ({})", e)),
+                        }
+                        if version < latest {
+                            println!("  [WARN] Schema version {} is behind {}, the scanner migrates it on start", version, latest);
+                        }
+                    }
+                    Err(e) => problem(format!("Failed to read the schema version ({})", e)),
+                },
+                Err(e) => problem(format!("Failed to connect to database ({})", e)),
+            }
+        }
+        Err(e) => {
+            println!("Database");
+            problem(e);
+        }
+    }
+
+    println!("Targets ({})", target_file);
+    match try_read_lines(target_file) {
+        Some(targets) => println!("  [ OK ] {} target players", targets.len()),
+        None => println!("  [WARN] Target file could not be read, no targets will be alerted"),
+    }
+
+    if let Some(opt_out_file) = &config.opt_out_file {
+        println!("Opt outs ({})", opt_out_file);
+        match try_read_lines(opt_out_file) {
+            Some(names) => println!("  [ OK ] {} opted out players", names.len()),
+            None => problem("Opt out file could not be read, opted out players would be recorded".to_string()),
+        }
+    }
+
+    println!("Servers ({})", server_file);
+    let mut servers: Vec<SocketAddr> = Vec::new();
+    match try_read_lines(server_file) {
+        Some(lines) => {
+            for (number, line) in lines.iter().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+                match line.trim().parse() {
+                    Ok(address) => servers.push(address),
+                    Err(_) => problem(format!("line {} is not a server address ({})", number + 1, line)),
+                }
+            }
+        }
+        None => problem("Failed to read target server file".to_string()),
+    }
+
+    let a2s_client = new_a2s_client();
+    for server in &servers {
+        let start = Instant::now();
+        match a2s_client.info(server) {
+            Ok(info) => println!("  [ OK ] {} ({}ms) {} : {} ({}/{})", server, start.elapsed().as_millis(), info.name, info.map, info.players, info.max_players),
+            Err(e) => problem(format!("{} did not answer ({})", server, e)),
+        }
+    }
+
+    match problems {
+        0 => println!("No problems found"),
+        _ => {
+            println!("{} problem(s) found", problems);
+            exit(1)
+        }
+    }
+}
+
+//One shot dump of the info, players and rules of a server
+pub fn query(address: &str) {
+    let a2s_client = new_a2s_client();
+
+    let start = Instant::now();
+    let info = match a2s_client.info(address) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Server Query Failed ({})", e);
+            exit(1)
+        }
+    };
+
+    println!("Info ({}ms)", start.elapsed().as_millis());
+    println!("  Name:        {}", info.name);
+    println!("  Map:         {}", info.map);
+    println!("  Game:        {} ({})", info.game, info.app_id);
+    println!("  Players:     {}/{} ({} bots)", info.players, info.max_players, info.bots);
+    println!("  Version:     {}", info.version);
+    println!("  VAC:         {}", info.vac);
+    println!("  Password:    {}", info.visibility);
+    println!("  Keywords:    {}", info.extended_server_info.keywords.unwrap_or_default());
+
+    match a2s_client.players(address) {
+        Ok(players) => {
+            println!("Players ({})", players.len());
+            for player in players {
+                println!("  {:>5} {:>9}  {}", player.score, format_duration(player.duration as usize), player.name);
+            }
+        }
+        Err(e) => println!("Player Query Failed ({})", e),
+    }
+
+    match a2s_client.rules(address) {
+        Ok(rules) => {
+            println!("Rules ({})", rules.len());
+            for rule in rules {
+                println!("  {} = {}", rule.name, rule.value);
+            }
+        }
+        Err(e) => println!("Rules Query Failed ({})", e),
+    }
+}
+
+pub fn pseudonym(config: &Config, name: &str) {
+    match &config.pseudonym_secret {
+        Some(secret) => println!("{}", pseudonym::Pseudonymiser::new(secret).name(name)),
+        None => {
+            eprintln!("No pseudonym_secret configured, names are stored as they are");
+            exit(1)
+        }
+    }
+}
+
+pub fn db_migrate(storage: &mut dyn Storage, applied: usize) {
+    match storage.schema_version() {
+        Ok((version, _)) => println!("Applied {} migrations, schema version {}", applied, version),
+        Err(e) => {
+            eprintln!("Failed to read schema version ({})", e);
+            exit(1)
+        }
+    }
+}
+
+pub fn db_stats(storage: &mut dyn Storage, backend: &Backend) {
+    let stats = match storage.stats() {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Failed to read database ({})", e);
+            exit(1)
+        }
+    };
+
+    println!("Database ({})", backend);
+    match stats.free_bytes {
+        Some(free) => println!("  Size:        {} KiB ({} KiB free)", stats.size_bytes / 1024, free / 1024),
+        None => println!("  Size:        {} KiB", stats.size_bytes / 1024),
+    }
+    for (table, rows) in &stats.tables {
+        println!("  {:<18} {} rows", table, rows);
+    }
+}
+
+pub fn db_vacuum(connection: &Connection, db_file: &str) {
+    let size_before = fs::metadata(db_file).map(|metadata| metadata.len()).unwrap_or(0);
+    let start = Instant::now();
+
+    if let Err(e) = sql::vacuum(connection) {
+        eprintln!("Failed to vacuum database ({})", e);
+        exit(1)
+    }
+
+    let size_after = fs::metadata(db_file).map(|metadata| metadata.len()).unwrap_or(0);
+    println!("Vacuumed ({}) in {}ms, {} KiB -> {} KiB", db_file, start.elapsed().as_millis(), size_before / 1024, size_after / 1024);
+}
+
+pub fn db_analyze(connection: &Connection) {
+    let start = Instant::now();
+    if let Err(e) = sql::analyze(connection) {
+        eprintln!("Failed to analyze database ({})", e);
+        exit(1)
+    }
+    println!("Analyzed in {}ms", start.elapsed().as_millis());
+}
+
+pub fn db_check(connection: &Connection, db_file: &str) {
+    let mut problems = 0;
+    println!("Database ({})", db_file);
+
+    match sql::integrity_check(connection) {
+        Ok(messages) if messages.is_empty() => println!("  [ OK ] Integrity check passed"),
+        Ok(messages) => {
+            for message in &messages {
+                println!("  [FAIL] {}", message);
+            }
+            problems += messages.len();
+        }
+        Err(e) => {
+            eprintln!("Failed to check database ({})", e);
+            exit(1)
+        }
+    }
+
+    match sql::foreign_key_violations(connection) {
+        Ok(violations) if violations.is_empty() => println!("  [ OK ] Every row points at an existing server and player"),
+        Ok(violations) => {
+            for (table, parent, rows) in &violations {
+                println!("  [FAIL] {} rows in {} point at missing {}", rows, table, parent);
+            }
+            problems += violations.len();
+        }
+        Err(e) => {
+            eprintln!("Failed to check foreign keys ({})", e);
+            exit(1)
+        }
+    }
+
+    match problems {
+        0 => println!("No problems found"),
+        _ => {
+            println!("{} problem(s) found", problems);
+            exit(1)
+        }
+    }
+}
+
+//Written next to the target and renamed once complete, so an interrupted backup never replaces a good one
+pub fn db_backup(connection: &Connection, path: &str) {
+    let partial = format!("{}.partial", path);
+    let _ = fs::remove_file(&partial);
+    let start = Instant::now();
+
+    if let Err(e) = sql::backup(connection, &partial).map_err(|e| e.to_string()).and_then(|_| fs::rename(&partial, path).map_err(|e| e.to_string())) {
+        let _ = fs::remove_file(&partial);
+        eprintln!("Failed to back up database ({})", e);
+        exit(1)
+    }
+
+    let size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    println!("Backed up to ({}) in {}ms, {} KiB", path, start.elapsed().as_millis(), size / 1024);
+}
+
+pub fn db_prune(storage: &mut dyn Storage, config: &Config) {
+    let policy = match retention::Policy::from_config(config) {
+        Ok(policy) if policy.is_empty() => {
+            println!("No retention windows configured, nothing to prune");
+            return;
+        }
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    };
+
+    let start = Instant::now();
+    match retention::prune(storage, &policy, &Mutex::new(())) {
+        Ok(pruned) => {
+            for (table, rows) in pruned {
+                println!("  {:<18} {} rows deleted", table, rows);
+            }
+            match storage.sqlite() {
+                Some(_) => println!("Pruned in {}ms, run db vacuum to shrink the file", start.elapsed().as_millis()),
+                None => println!("Pruned in {}ms", start.elapsed().as_millis()),
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to prune database ({})", e);
+            exit(1)
+        }
+    }
+}
+
+pub fn db_pseudonymise(connection: &mut Connection, config: &Config, keep_hashed: bool) {
+    let pseudonymiser = match &config.pseudonym_secret {
+        Some(secret) => pseudonym::Pseudonymiser::new(secret),
+        None => {
+            eprintln!("No pseudonym_secret configured");
+            exit(1)
+        }
+    };
+
+    //A name kept as it is only gets marked, a pseudonym can't be checked without the name it came from
+    let looks_hashed = |name: &str| name.len() == 32 && name.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+    let mut kept = 0;
+    let renames: Vec<(i32, String)> = match sql::get_plain_players(connection) {
+        Ok(players) => players
+            .into_iter()
+            .map(|player| match keep_hashed && looks_hashed(&player.name) {
+                true => {
+                    kept += 1;
+                    (player.player_id, player.name)
+                }
+                false => (player.player_id, pseudonymiser.name(&player.name)),
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to read players ({})", e);
+            exit(1)
+        }
+    };
+
+    match sql::rename_players(connection, &renames) {
+        Ok(renamed) => println!("Replaced {} player names and kept {} as pseudonyms, run db vacuum so the old names don't linger in free pages", renamed - kept, kept),
+        Err(e) => {
+            eprintln!("Failed to replace player names ({})", e);
+            exit(1)
+        }
+    }
+}
+
+pub fn db_purge_player(storage: &mut dyn Storage, config: &Config, name: &str) {
+    let pseudonymiser = config.pseudonym_secret.as_deref().map(pseudonym::Pseudonymiser::new);
+    match storage.delete_player(&pseudonym::stored(pseudonymiser.as_ref(), name)) {
+        Ok(Some((sessions, events))) => {
+            println!("Deleted {} with {} sessions and {} events", name, sessions, events);
+            if !config.opt_out_file.as_deref().and_then(try_read_lines).unwrap_or_default().iter().any(|line| line == name) {
+                println!("Add the name to the opt out file or the scanner will record them again");
+            }
+        }
+        Ok(None) => {
+            eprintln!("No player named {} in the database", name);
+            exit(1)
+        }
+        Err(e) => {
+            eprintln!("Failed to delete player ({})", e);
+            exit(1)
+        }
+    }
+}
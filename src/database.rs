@@ -0,0 +1,166 @@
+use crate::{pgsql, sql, storage::{Backend, Result}};
+use postgres::{fallible_iterator::FallibleIterator, types::{ToSql, Type}, Client};
+use rusqlite::Connection;
+
+//Read access for tf2-analysis and tf2-api. Queries are written once with ?N parameters in sql both backends understand,
+//postgres gets them as $N.
+pub enum Database {
+    Sqlite(Connection),
+    Postgres(Client),
+}
+
+//Postgres can't infer the type of a parameter that is only compared with NULL, so every parameter carries one
+pub enum Param {
+    Integer(Option<i64>),
+    Text(Option<String>),
+}
+
+impl From<i64> for Param {
+    fn from(value: i64) -> Param {
+        Param::Integer(Some(value))
+    }
+}
+
+impl From<Option<i64>> for Param {
+    fn from(value: Option<i64>) -> Param {
+        Param::Integer(value)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(value: &str) -> Param {
+        Param::Text(Some(value.to_string()))
+    }
+}
+
+impl From<&Option<String>> for Param {
+    fn from(value: &Option<String>) -> Param {
+        Param::Text(value.clone())
+    }
+}
+
+//A value both drivers can read
+pub trait Column: rusqlite::types::FromSql + for<'a> postgres::types::FromSql<'a> {}
+
+impl<T: rusqlite::types::FromSql + for<'a> postgres::types::FromSql<'a>> Column for T {}
+
+pub enum Row<'a> {
+    Sqlite(&'a rusqlite::Row<'a>),
+    Postgres(&'a postgres::Row),
+}
+
+impl Row<'_> {
+    pub fn get<T: Column>(&self, index: usize) -> Result<T> {
+        match self {
+            Row::Sqlite(row) => Ok(row.get(index)?),
+            Row::Postgres(row) => Ok(row.try_get(index)?),
+        }
+    }
+}
+
+impl Database {
+    //Sqlite files are opened read only, postgres sessions refuse writes
+    pub fn open(backend: &Backend) -> Result<Database> {
+        match backend {
+            Backend::Sqlite(path, _) => Ok(Database::Sqlite(sql::open_read_only(path)?)),
+            Backend::Postgres(url) => Ok(Database::Postgres(pgsql::connect_read_only_client(url)?)),
+        }
+    }
+
+    //A postgres connection is gone for good once the server closed it, sqlite files don't go away
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Database::Sqlite(_) => false,
+            Database::Postgres(client) => client.is_closed(),
+        }
+    }
+
+    //Calls f for every row as it is read, without holding the result in memory
+    pub fn for_each(&mut self, query: &str, params: &[Param], mut f: impl FnMut(&Row) -> Result<()>) -> Result<()> {
+        match self {
+            Database::Sqlite(conn) => {
+                let values: Vec<&dyn rusqlite::ToSql> = params
+                    .iter()
+                    .map(|param| match param {
+                        Param::Integer(value) => value as &dyn rusqlite::ToSql,
+                        Param::Text(value) => value as &dyn rusqlite::ToSql,
+                    })
+                    .collect();
+                let mut stmt = conn.prepare(query)?;
+                let mut rows = stmt.query(values.as_slice())?;
+                while let Some(row) = rows.next()? {
+                    f(&Row::Sqlite(row))?;
+                }
+            }
+            Database::Postgres(client) => {
+                let types: Vec<Type> = params
+                    .iter()
+                    .map(|param| match param {
+                        Param::Integer(_) => Type::INT8,
+                        Param::Text(_) => Type::TEXT,
+                    })
+                    .collect();
+                let values: Vec<&(dyn ToSql + Sync)> = params
+                    .iter()
+                    .map(|param| match param {
+                        Param::Integer(value) => value as &(dyn ToSql + Sync),
+                        Param::Text(value) => value as &(dyn ToSql + Sync),
+                    })
+                    .collect();
+                let statement = client.prepare_typed(&numbered_params(query), &types)?;
+                let mut rows = client.query_raw(&statement, values)?;
+                while let Some(row) = rows.next()? {
+                    f(&Row::Postgres(&row))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn query<T>(&mut self, query: &str, params: &[Param], mut map: impl FnMut(&Row) -> Result<T>) -> Result<Vec<T>> {
+        let mut rows = Vec::new();
+        self.for_each(query, params, |row| {
+            rows.push(map(row)?);
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    pub fn query_one<T>(&mut self, query: &str, params: &[Param], map: impl FnMut(&Row) -> Result<T>) -> Result<T> {
+        match self.query(query, params, map)?.into_iter().next() {
+            Some(row) => Ok(row),
+            None => Err("Query returned no rows".into()),
+        }
+    }
+
+    pub fn has_table(&mut self, table: &str) -> Result<bool> {
+        let query = match self {
+            Database::Sqlite(_) => "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            Database::Postgres(_) => "SELECT to_regclass(?1) IS NOT NULL",
+        };
+        self.query_one(query, &[table.into()], |row| row.get(0))
+    }
+}
+
+//?1 to $1, none of the queries have a question mark anywhere else
+fn numbered_params(query: &str) -> String {
+    query.replace('?', "$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_params_for_postgres() {
+        assert_eq!(numbered_params("SELECT ?1 WHERE (?2 IS NULL OR x = ?2) LIMIT ?10"), "SELECT $1 WHERE ($2 IS NULL OR x = $2) LIMIT $10");
+    }
+
+    #[test]
+    fn reads_sqlite() {
+        let mut database = Database::Sqlite(Connection::open_in_memory().unwrap());
+        let rows = database.query("SELECT ?1 + 1, ?2, ?3 IS NULL", &[1.into(), "a".into(), Param::Integer(None)], |row| Ok((row.get::<i64>(0)?, row.get::<String>(1)?, row.get::<bool>(2)?))).unwrap();
+        assert_eq!(rows, vec![(2, "a".to_string(), true)]);
+        assert!(!database.has_table("servers").unwrap());
+    }
+}
@@ -0,0 +1,53 @@
+//Longest duration accepted, far beyond any retention window or report range but small enough to subtract from now
+const MAX_SECONDS: i64 = 100 * 365 * 86400;
+
+//Duration in seconds from "90s", "30m", "12h", "7d" or "2w"
+pub fn parse_duration(input: &str) -> Result<i64, String> {
+    let (number, unit) = input.split_at(input.trim_end_matches(char::is_alphabetic).len());
+    let number: i64 = match number.parse() {
+        Ok(number) if number >= 0 => number,
+        _ => return Err(format!("Invalid time ({})", input)),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return Err(format!("Invalid time unit ({}), expected s, m, h, d or w", input)),
+    };
+    match number.checked_mul(unit) {
+        Some(seconds) if seconds <= MAX_SECONDS => Ok(seconds),
+        _ => Err(format!("Time too long ({}), at most 100 years", input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_unit() {
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert_eq!(parse_duration("12h"), Ok(43200));
+        assert_eq!(parse_duration("7d"), Ok(604800));
+        assert_eq!(parse_duration("2w"), Ok(1209600));
+        assert_eq!(parse_duration("0s"), Ok(0));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        for input in ["", "m", "5", "5y", "5 m", "1.5h", "-5m", "s5"] {
+            assert!(parse_duration(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn rejects_overflow_and_huge_values() {
+        assert!(parse_duration("9223372036854775807w").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert!(parse_duration("5300w").is_err());
+        assert_eq!(parse_duration("5200w"), Ok(5200 * 604800));
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::Local;
+use json::JsonValue;
+use std::{io::Write, sync::{atomic::{AtomicUsize, Ordering}, mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender}, Arc, Mutex}, time::Duration};
+use tiny_http::Request;
+
+//Comment line sent when nothing happened, so dead clients are noticed and proxies keep the connection open
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+//Http streams served at once, each holds a thread and a connection for as long as its client stays
+pub const MAX_STREAMS: usize = 64;
+
+//Events queued for one client, a client that falls this far behind is dropped instead of buffering without end
+const QUEUE: usize = 256;
+
+//Fans live events out to every connected stream client
+#[derive(Default)]
+pub struct Broadcaster {
+    clients: Mutex<Vec<SyncSender<JsonValue>>>,
+    streams: Arc<AtomicUsize>,
+}
+
+//Receiver of an http stream, frees its slot when the stream ends
+pub struct Subscription {
+    receiver: Receiver<JsonValue>,
+    streams: Arc<AtomicUsize>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Broadcaster {
+    pub fn subscribe(&self) -> Receiver<JsonValue> {
+        let (sender, receiver) = sync_channel(QUEUE);
+        self.clients.lock().unwrap().push(sender);
+        receiver
+    }
+
+    //None once MAX_STREAMS streams are open
+    pub fn subscribe_stream(&self) -> Option<Subscription> {
+        self.streams.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| (open < MAX_STREAMS).then_some(open + 1)).ok()?;
+        Some(Subscription { receiver: self.subscribe(), streams: self.streams.clone() })
+    }
+
+    //Sends the data to every client, adding the kind as `type` and the time
+    pub fn publish(&self, kind: &str, mut data: JsonValue) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+
+        data["type"] = kind.into();
+        data["time"] = Local::now().to_rfc3339().into();
+
+        //A failed send means the client has gone away or its queue is full
+        clients.retain(|client| client.try_send(data.clone()).is_ok());
+    }
+}
+
+//Holds the request open as a text/event-stream, `event: <type>` then the json data, until the client goes away
+pub fn stream(request: Request, subscription: Subscription) {
+    let mut writer = request.into_writer();
+    let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\nAccess-Control-Allow-Origin: *\r\n\r\n";
+    if writer.write_all(headers.as_bytes()).and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+
+    loop {
+        let message = match subscription.receiver.recv_timeout(KEEPALIVE) {
+            Ok(data) => format!("event: {}\ndata: {}\n\n", data["type"], data.dump()),
+            Err(RecvTimeoutError::Timeout) => ": keepalive\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if writer.write_all(message.as_bytes()).and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_a_client_that_falls_behind() {
+        let broadcaster = Broadcaster::default();
+        let slow = broadcaster.subscribe();
+        let fast = broadcaster.subscribe();
+        for i in 0..QUEUE + 1 {
+            broadcaster.publish("test", object! { i: i });
+            assert!(fast.try_recv().is_ok());
+        }
+
+        assert_eq!(broadcaster.clients.lock().unwrap().len(), 1);
+        assert_eq!(slow.try_iter().count(), QUEUE);
+        assert!(slow.try_recv().is_err());
+    }
+}
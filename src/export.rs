@@ -0,0 +1,562 @@
+use crate::queries::Filter;
+use parquet::{
+    basic::Compression,
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use rusqlite::types::ValueRef;
+use std::{error::Error, io::{self, Write}, str::FromStr, sync::Arc};
+use tf2_surveillance::database::{Database, Row};
+
+//Rows buffered per parquet row group, the only rows held in memory at once
+const ROW_GROUP_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(format!("Unknown format ({}), expected csv, jsonl or parquet", input)),
+        }
+    }
+}
+
+impl Format {
+    //Format implied by an output file name
+    pub fn from_path(path: &str) -> Option<Format> {
+        path.rsplit_once('.').and_then(|(_, extension)| extension.to_lowercase().parse().ok())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Integer,
+    Real,
+    Text,
+    Boolean,
+    //Utc unix milliseconds
+    Time,
+}
+
+#[derive(Debug)]
+enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Boolean(bool),
+}
+
+struct Source {
+    name: &'static str,
+    columns: &'static [(&'static str, Kind)],
+    //Condition on t, the exported table, using ?1 since, ?2 until and ?3 server
+    filter: &'static str,
+}
+
+const SERVER_FILTER: &str = "(?3 IS NULL OR t.server_id IN (SELECT server_id FROM servers WHERE address = ?3))";
+
+const SOURCES: [Source; 7] = [
+    Source {
+        name: "servers",
+        columns: &[("server_id", Kind::Integer), ("address", Kind::Text)],
+        filter: "(?3 IS NULL OR t.address = ?3)",
+    },
+    Source {
+        name: "server_settings",
+        columns: &[
+            ("setting_id", Kind::Integer),
+            ("server_id", Kind::Integer),
+            ("name", Kind::Text),
+            ("max_players", Kind::Integer),
+            ("current_map", Kind::Text),
+            ("vac_status", Kind::Boolean),
+            ("has_password", Kind::Boolean),
+            ("game_version", Kind::Text),
+            ("bots", Kind::Integer),
+            ("created_at", Kind::Time),
+        ],
+        filter: "(?1 IS NULL OR t.created_at >= ?1) AND (?2 IS NULL OR t.created_at < ?2)",
+    },
+    //Players have no time of their own, so filters keep those with a session in range
+    Source {
+        name: "players",
+        columns: &[("player_id", Kind::Integer), ("name", Kind::Text)],
+        filter: "((?1 IS NULL AND ?2 IS NULL AND ?3 IS NULL) OR EXISTS (
+                SELECT 1 FROM sessions s JOIN servers v ON v.server_id = s.server_id
+                WHERE s.player_id = t.player_id AND (?1 IS NULL OR s.left_at >= ?1) AND (?2 IS NULL OR s.joined_at < ?2) AND (?3 IS NULL OR v.address = ?3)))",
+    },
+    Source {
+        name: "sessions",
+        columns: &[
+            ("session_id", Kind::Integer),
+            ("server_id", Kind::Integer),
+            ("player_id", Kind::Integer),
+            ("score", Kind::Integer),
+            ("duration", Kind::Real),
+            ("joined_at", Kind::Time),
+            ("left_at", Kind::Time),
+        ],
+        filter: "(?1 IS NULL OR t.joined_at >= ?1) AND (?2 IS NULL OR t.joined_at < ?2)",
+    },
+    Source {
+        name: "server_events",
+        columns: &[("event_id", Kind::Integer), ("server_id", Kind::Integer), ("event_type", Kind::Text), ("event_data", Kind::Text), ("created_at", Kind::Time)],
+        filter: "(?1 IS NULL OR t.created_at >= ?1) AND (?2 IS NULL OR t.created_at < ?2)",
+    },
+    Source {
+        name: "player_events",
+        columns: &[
+            ("event_id", Kind::Integer),
+            ("server_id", Kind::Integer),
+            ("player_id", Kind::Integer),
+            ("event_type", Kind::Text),
+            ("event_data", Kind::Text),
+            ("created_at", Kind::Time),
+        ],
+        filter: "(?1 IS NULL OR t.created_at >= ?1) AND (?2 IS NULL OR t.created_at < ?2)",
+    },
+    Source {
+        name: "population_samples",
+        columns: &[
+            ("sample_id", Kind::Integer),
+            ("server_id", Kind::Integer),
+            ("players", Kind::Integer),
+            ("bots", Kind::Integer),
+            ("players_joined", Kind::Integer),
+            ("players_left", Kind::Integer),
+            ("created_at", Kind::Time),
+        ],
+        filter: "(?1 IS NULL OR t.created_at >= ?1) AND (?2 IS NULL OR t.created_at < ?2)",
+    },
+];
+
+pub fn tables() -> Vec<&'static str> {
+    SOURCES.iter().map(|source| source.name).collect()
+}
+
+//Streams the table row by row into the writer, returns the number of rows written
+pub fn export(database: &mut Database, table: &str, filter: &Filter, format: Format, out: Box<dyn Write + Send>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let source = match SOURCES.iter().find(|source| source.name == table) {
+        Some(source) => source,
+        None => return Err(format!("Unknown table ({}), expected {}", table, tables().join(", ")).into()),
+    };
+
+    //Postgres has 32 bit integer columns, read as 64 bit like sqlite's
+    let columns: Vec<String> = source
+        .columns
+        .iter()
+        .map(|(name, kind)| match (&database, kind) {
+            (Database::Postgres(_), Kind::Integer | Kind::Time) => format!("CAST(t.{} AS BIGINT)", name),
+            _ => format!("t.{}", name),
+        })
+        .collect();
+    let server_filter = if source.name == "servers" || source.name == "players" { "TRUE" } else { SERVER_FILTER };
+    //The primary key is always the first column
+    let sql = format!(
+        "SELECT {} FROM {} t WHERE {} AND {} ORDER BY t.{}",
+        columns.join(", "),
+        source.name,
+        source.filter,
+        server_filter,
+        source.columns[0].0
+    );
+
+    let mut writer: Box<dyn RowWriter> = match format {
+        Format::Csv => Box::new(CsvWriter::new(out, source)?),
+        Format::Jsonl => Box::new(JsonlWriter { out, source }),
+        Format::Parquet => Box::new(ParquetWriter::new(out, source)?),
+    };
+
+    let mut count = 0;
+    database.for_each(&sql, &filter.params(), |row| {
+        let values = source.columns.iter().enumerate().map(|(index, (_, kind))| value(row, index, *kind)).collect::<Result<Vec<Value>, _>>()?;
+        writer.write(values)?;
+        count += 1;
+        Ok(())
+    })?;
+    writer.finish()?;
+    Ok(count)
+}
+
+//A reader that went away, eg. piping into head, is not a failed export
+pub fn is_broken_pipe(error: &(dyn Error + 'static)) -> bool {
+    if let Some(ParquetError::External(error)) = error.downcast_ref::<ParquetError>() {
+        return is_broken_pipe(error.as_ref());
+    }
+    let io = match error.downcast_ref::<csv::Error>() {
+        Some(error) => match error.kind() {
+            csv::ErrorKind::Io(error) => Some(error),
+            _ => None,
+        },
+        None => error.downcast_ref::<io::Error>(),
+    };
+    io.map(|error| error.kind() == io::ErrorKind::BrokenPipe).unwrap_or(false)
+}
+
+//Sqlite columns can hold any type, so its values are converted by what they hold, postgres ones by their column type
+fn value(row: &Row, index: usize, kind: Kind) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    match row {
+        Row::Sqlite(row) => Ok(sqlite_value(row.get_ref(index)?, kind)),
+        Row::Postgres(_) => Ok(match kind {
+            Kind::Integer | Kind::Time => row.get::<Option<i64>>(index)?.map(Value::Integer),
+            Kind::Real => row.get::<Option<f64>>(index)?.map(Value::Real),
+            Kind::Text => row.get::<Option<String>>(index)?.map(Value::Text),
+            Kind::Boolean => row.get::<Option<bool>>(index)?.map(Value::Boolean),
+        }
+        .unwrap_or(Value::Null)),
+    }
+}
+
+fn sqlite_value(raw: ValueRef, kind: Kind) -> Value {
+    match (raw, kind) {
+        (ValueRef::Null, _) => Value::Null,
+        (ValueRef::Integer(number), Kind::Boolean) => Value::Boolean(number != 0),
+        (ValueRef::Integer(number), Kind::Real) => Value::Real(number as f64),
+        (ValueRef::Integer(number), Kind::Integer | Kind::Time) => Value::Integer(number),
+        (ValueRef::Real(number), Kind::Integer | Kind::Real) => Value::Real(number),
+        (ValueRef::Text(text), _) => Value::Text(String::from_utf8_lossy(text).into_owned()),
+        (ValueRef::Integer(number), _) => Value::Text(number.to_string()),
+        (ValueRef::Real(number), _) => Value::Text(number.to_string()),
+        (ValueRef::Blob(_), _) => Value::Null,
+    }
+}
+
+trait RowWriter {
+    fn write(&mut self, values: Vec<Value>) -> Result<(), Box<dyn Error + Send + Sync>>;
+    fn finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+struct CsvWriter {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+}
+
+impl CsvWriter {
+    fn new(out: Box<dyn Write + Send>, source: &Source) -> Result<CsvWriter, Box<dyn Error + Send + Sync>> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(source.columns.iter().map(|(name, _)| name))?;
+        Ok(CsvWriter { writer })
+    }
+}
+
+impl RowWriter for CsvWriter {
+    fn write(&mut self, values: Vec<Value>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.writer.write_record(values.into_iter().map(|value| match value {
+            Value::Null => String::new(),
+            Value::Integer(number) => number.to_string(),
+            Value::Real(number) => number.to_string(),
+            Value::Text(text) => text,
+            Value::Boolean(flag) => flag.to_string(),
+        }))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+struct JsonlWriter {
+    out: Box<dyn Write + Send>,
+    source: &'static Source,
+}
+
+impl RowWriter for JsonlWriter {
+    fn write(&mut self, values: Vec<Value>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut object = json::JsonValue::new_object();
+        for ((name, _), value) in self.source.columns.iter().zip(values) {
+            object[*name] = match value {
+                Value::Null => json::JsonValue::Null,
+                Value::Integer(number) => number.into(),
+                Value::Real(number) => number.into(),
+                Value::Text(text) => text.into(),
+                Value::Boolean(flag) => flag.into(),
+            };
+        }
+        writeln!(self.out, "{}", object.dump())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.out.flush()?)
+    }
+}
+
+//One column of the row group being built, with a definition level per row for nulls
+enum ColumnBuffer {
+    Integer(Vec<i64>, Vec<i16>),
+    Real(Vec<f64>, Vec<i16>),
+    Text(Vec<ByteArray>, Vec<i16>),
+    Boolean(Vec<bool>, Vec<i16>),
+}
+
+struct ParquetWriter {
+    writer: Option<SerializedFileWriter<Box<dyn Write + Send>>>,
+    columns: Vec<ColumnBuffer>,
+    rows: usize,
+}
+
+impl ParquetWriter {
+    fn new(out: Box<dyn Write + Send>, source: &Source) -> Result<ParquetWriter, Box<dyn Error + Send + Sync>> {
+        //Every column is optional so the schema doesn't depend on the table's constraints
+        let fields: Vec<String> = source
+            .columns
+            .iter()
+            .map(|(name, kind)| match kind {
+                Kind::Integer => format!("OPTIONAL INT64 {};", name),
+                Kind::Real => format!("OPTIONAL DOUBLE {};", name),
+                Kind::Time => format!("OPTIONAL INT64 {} (TIMESTAMP(MILLIS,true));", name),
+                Kind::Text => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+                Kind::Boolean => format!("OPTIONAL BOOLEAN {};", name),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message {} {{ {} }}", source.name, fields.join(" ")))?;
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+
+        Ok(ParquetWriter {
+            writer: Some(SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties))?),
+            columns: source
+                .columns
+                .iter()
+                .map(|(_, kind)| match kind {
+                    Kind::Integer | Kind::Time => ColumnBuffer::Integer(Vec::new(), Vec::new()),
+                    Kind::Real => ColumnBuffer::Real(Vec::new(), Vec::new()),
+                    Kind::Text => ColumnBuffer::Text(Vec::new(), Vec::new()),
+                    Kind::Boolean => ColumnBuffer::Boolean(Vec::new(), Vec::new()),
+                })
+                .collect(),
+            rows: 0,
+        })
+    }
+
+    fn flush_row_group(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let writer = self.writer.as_mut().expect("parquet writer used after finish");
+        let mut row_group = writer.next_row_group()?;
+        let mut columns = self.columns.iter_mut();
+        while let Some(mut column) = row_group.next_column()? {
+            match columns.next().expect("schema matches the buffered columns") {
+                ColumnBuffer::Integer(values, levels) => column.typed::<Int64Type>().write_batch(values, Some(levels), None)?,
+                ColumnBuffer::Real(values, levels) => column.typed::<DoubleType>().write_batch(values, Some(levels), None)?,
+                ColumnBuffer::Text(values, levels) => column.typed::<ByteArrayType>().write_batch(values, Some(levels), None)?,
+                ColumnBuffer::Boolean(values, levels) => column.typed::<BoolType>().write_batch(values, Some(levels), None)?,
+            };
+            column.close()?;
+        }
+        row_group.close()?;
+
+        for column in &mut self.columns {
+            match column {
+                ColumnBuffer::Integer(values, levels) => (values.clear(), levels.clear()),
+                ColumnBuffer::Real(values, levels) => (values.clear(), levels.clear()),
+                ColumnBuffer::Text(values, levels) => (values.clear(), levels.clear()),
+                ColumnBuffer::Boolean(values, levels) => (values.clear(), levels.clear()),
+            };
+        }
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl RowWriter for ParquetWriter {
+    fn write(&mut self, values: Vec<Value>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for (column, value) in self.columns.iter_mut().zip(values) {
+            //Nulls only get a definition level of 0, present values a 1 and the value
+            match (column, value) {
+                (ColumnBuffer::Integer(_, levels) | ColumnBuffer::Real(_, levels) | ColumnBuffer::Text(_, levels) | ColumnBuffer::Boolean(_, levels), Value::Null) => levels.push(0),
+                (ColumnBuffer::Integer(values, levels), Value::Integer(number)) => {
+                    values.push(number);
+                    levels.push(1)
+                }
+                (ColumnBuffer::Integer(values, levels), Value::Real(number)) => {
+                    values.push(number as i64);
+                    levels.push(1)
+                }
+                (ColumnBuffer::Real(values, levels), Value::Real(number)) => {
+                    values.push(number);
+                    levels.push(1)
+                }
+                (ColumnBuffer::Boolean(values, levels), Value::Boolean(flag)) => {
+                    values.push(flag);
+                    levels.push(1)
+                }
+                (ColumnBuffer::Text(values, levels), Value::Text(text)) => {
+                    values.push(ByteArray::from(text.into_bytes()));
+                    levels.push(1)
+                }
+                (_, value) => return Err(format!("Unexpected value for column type ({:?})", value).into()),
+            }
+        }
+        self.rows += 1;
+        if self.rows >= ROW_GROUP_SIZE {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.rows > 0 {
+            self.flush_row_group()?;
+        }
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::to_millis;
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+    use parquet::{file::reader::{FileReader, SerializedFileReader}, record::{Field, RowAccessor}};
+    use rusqlite::{params, Connection};
+    use std::sync::Mutex;
+
+    //Collects what an export writes, the writers take ownership of their output
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 10).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    fn at(minutes: i64) -> i64 {
+        to_millis(start() + Duration::minutes(minutes))
+    }
+
+    //Two servers, a plays on the first for the first hour, b on the second in the third and c never
+    fn database() -> Database {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        conn.execute_batch("INSERT INTO servers (address) VALUES ('127.0.0.1:27015'), ('127.0.0.1:27016'); INSERT INTO players (name) VALUES ('a'), ('b'), ('c');").unwrap();
+        for (server_id, player_id, joined, left) in [(1, 1, 0, 60), (2, 2, 120, 180)] {
+            conn.execute(
+                "INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES (?1, ?2, 5, 3600.5, ?3, ?4)",
+                params![server_id, player_id, at(joined), at(left)],
+            ).unwrap();
+        }
+        for (server_id, event_type, event_data, minutes) in [(1, "up", None, 0), (1, "setting change", Some("{\"map\":\"pl_upward\"}"), 30), (2, "up", None, 150)] {
+            conn.execute(
+                "INSERT INTO server_events (server_id, event_type, event_data, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![server_id, event_type, event_data, at(minutes)],
+            ).unwrap();
+        }
+        conn.execute(
+            "INSERT INTO server_settings (server_id, name, max_players, current_map, vac_status, has_password, game_version, bots, created_at) VALUES (1, 'test', 24, 'pl_upward', 1, 0, '8835751', 0, ?1)",
+            params![at(0)],
+        ).unwrap();
+        Database::Sqlite(conn)
+    }
+
+    fn export_string(database: &mut Database, table: &str, filter: &Filter, format: Format) -> String {
+        let buffer = Buffer::default();
+        export(database, table, filter, format, Box::new(buffer.clone())).unwrap();
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn first_column(csv: &str) -> Vec<&str> {
+        csv.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect()
+    }
+
+    #[test]
+    fn writes_csv_with_a_header_and_empty_nulls() {
+        let csv = export_string(&mut database(), "server_events", &Filter::default(), Format::Csv);
+        let expected = format!(
+            "event_id,server_id,event_type,event_data,created_at\n1,1,up,,{}\n2,1,setting change,\"{{\"\"map\"\":\"\"pl_upward\"\"}}\",{}\n3,2,up,,{}\n",
+            at(0),
+            at(30),
+            at(150)
+        );
+        assert_eq!(csv, expected);
+    }
+
+    #[test]
+    fn writes_jsonl_with_typed_values_and_nulls() {
+        let jsonl = export_string(&mut database(), "server_settings", &Filter::default(), Format::Jsonl);
+        let row = json::parse(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(row["max_players"], 24);
+        assert_eq!(row["vac_status"], true);
+        assert_eq!(row["created_at"], at(0));
+
+        let jsonl = export_string(&mut database(), "server_events", &Filter::default(), Format::Jsonl);
+        let rows: Vec<_> = jsonl.lines().map(|line| json::parse(line).unwrap()).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0]["event_data"].is_null());
+        assert_eq!(rows[1]["event_data"], "{\"map\":\"pl_upward\"}");
+    }
+
+    #[test]
+    fn writes_parquet_with_timestamps_and_nulls() {
+        let path = std::env::temp_dir().join(format!("tf2-export-test-{}.parquet", std::process::id()));
+        let rows = export(&mut database(), "server_events", &Filter::default(), Format::Parquet, Box::new(std::fs::File::create(&path).unwrap())).unwrap();
+        assert_eq!(rows, 3);
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|row| row.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1].get_long(0).unwrap(), 2);
+        assert_eq!(rows[1].get_string(3).unwrap(), "{\"map\":\"pl_upward\"}");
+        assert_eq!(rows[1].get_timestamp_millis(4).unwrap(), at(30));
+        assert_eq!(rows[0].get_column_iter().nth(3).unwrap().1, &Field::Null);
+    }
+
+    #[test]
+    fn filters_by_time_and_server() {
+        let mut database = database();
+        let since = Filter { since: Some(start() + Duration::minutes(30)), ..Default::default() };
+        let until = Filter { until: Some(start() + Duration::minutes(30)), ..Default::default() };
+        let server = Filter { server: Some("127.0.0.1:27016".to_string()), ..Default::default() };
+
+        assert_eq!(first_column(&export_string(&mut database, "server_events", &since, Format::Csv)), vec!["2", "3"]);
+        assert_eq!(first_column(&export_string(&mut database, "server_events", &until, Format::Csv)), vec!["1"]);
+        assert_eq!(first_column(&export_string(&mut database, "server_events", &server, Format::Csv)), vec!["3"]);
+        assert_eq!(first_column(&export_string(&mut database, "sessions", &since, Format::Csv)), vec!["2"]);
+        assert_eq!(first_column(&export_string(&mut database, "servers", &server, Format::Csv)), vec!["2"]);
+        assert!(first_column(&export_string(&mut database, "server_settings", &server, Format::Csv)).is_empty());
+    }
+
+    #[test]
+    fn keeps_players_with_a_session_in_range() {
+        let mut database = database();
+        let mut players = |filter: &Filter| export_string(&mut database, "players", filter, Format::Csv).lines().skip(1).map(str::to_string).collect::<Vec<_>>();
+
+        //Unfiltered every player is exported, also those without a session
+        assert_eq!(players(&Filter::default()), vec!["1,a", "2,b", "3,c"]);
+        //a's session runs until minute 60, so it overlaps a range starting at 30
+        assert_eq!(players(&Filter { since: Some(start() + Duration::minutes(30)), ..Default::default() }), vec!["1,a", "2,b"]);
+        assert_eq!(players(&Filter { since: Some(start() + Duration::minutes(90)), ..Default::default() }), vec!["2,b"]);
+        assert_eq!(players(&Filter { until: Some(start() + Duration::minutes(90)), ..Default::default() }), vec!["1,a"]);
+        assert_eq!(players(&Filter { server: Some("127.0.0.1:27015".to_string()), ..Default::default() }), vec!["1,a"]);
+    }
+
+    #[test]
+    fn rejects_unknown_tables() {
+        let error = export(&mut database(), "sqlite_master", &Filter::default(), Format::Csv, Box::new(Buffer::default())).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown table (sqlite_master)"));
+    }
+}
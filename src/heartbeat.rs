@@ -0,0 +1,89 @@
+//Totals from one scan, substituted into the heartbeat url and body templates
+pub struct ScanSummary {
+    pub scan_ms: u128,
+    pub servers_ok: usize,
+    pub servers_failed: usize,
+    pub players: usize,
+    pub events: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    #[default]
+    Get,
+    Post,
+}
+
+const PLACEHOLDERS: [&str; 5] = ["{scan_ms}", "{servers_ok}", "{servers_failed}", "{players}", "{events}"];
+
+//Older versions appended the scan time to the url for Uptime Kuma, a url without placeholders still gets it
+pub fn heartbeat_url(url: &str) -> String {
+    match has_placeholders(url) {
+        true => url.to_string(),
+        false => format!("{}{{scan_ms}}", url),
+    }
+}
+
+pub fn has_placeholders(template: &str) -> bool {
+    PLACEHOLDERS.iter().any(|placeholder| template.contains(placeholder))
+}
+
+//Replace {scan_ms}, {servers_ok}, {servers_failed}, {players} and {events} in the template
+pub fn fill_template(template: &str, summary: &ScanSummary) -> String {
+    template
+        .replace("{scan_ms}", &summary.scan_ms.to_string())
+        .replace("{servers_ok}", &summary.servers_ok.to_string())
+        .replace("{servers_failed}", &summary.servers_failed.to_string())
+        .replace("{players}", &summary.players.to_string())
+        .replace("{events}", &summary.events.to_string())
+}
+
+pub fn send_heartbeat(url: &str, method: Method, body: Option<&str>, summary: &ScanSummary) -> bool {
+    let url = fill_template(url, summary);
+
+    let call = match (method, body) {
+        (Method::Get, _) => ureq::get(&url).call(),
+        (Method::Post, Some(body)) => ureq::post(&url)
+            .set("Content-Type", "application/json")
+            .send_string(&fill_template(body, summary)),
+        (Method::Post, None) => ureq::post(&url).call(),
+    };
+
+    match call {
+        Ok(_) => true,
+        Err(e) => {log::error!("Failed to send heartbeat ({})", e); false},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> ScanSummary {
+        ScanSummary { scan_ms: 1250, servers_ok: 3, servers_failed: 1, players: 42, events: 7 }
+    }
+
+    #[test]
+    fn fills_every_placeholder() {
+        let template = "https://example.com/push?ping={scan_ms}&ok={servers_ok}&failed={servers_failed}&players={players}&events={events}";
+        assert_eq!(fill_template(template, &summary()), "https://example.com/push?ping=1250&ok=3&failed=1&players=42&events=7");
+    }
+
+    #[test]
+    fn leaves_unknown_braces_alone() {
+        assert_eq!(fill_template(r#"{"ping": {scan_ms}, "other": {unknown}}"#, &summary()), r#"{"ping": 1250, "other": {unknown}}"#);
+    }
+
+    #[test]
+    fn appends_scan_time_without_placeholders() {
+        let url = heartbeat_url("https://uptime.example.com/api/push/TOKEN?status=up&msg=OK&ping=");
+        assert_eq!(fill_template(&url, &summary()), "https://uptime.example.com/api/push/TOKEN?status=up&msg=OK&ping=1250");
+    }
+
+    #[test]
+    fn keeps_urls_with_placeholders() {
+        let url = "https://example.com/push?players={players}";
+        assert_eq!(heartbeat_url(url), url);
+    }
+}
@@ -0,0 +1,4 @@
+//Text and attribute values for the svg and html the analysis, reports and status page write
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,14 @@
+//Modules shared by tf2-scan, tf2-analysis and tf2-api
+#[macro_use]
+extern crate json;
+#[macro_use]
+extern crate serde_derive;
+
+pub mod database;
+pub mod duration;
+pub mod html;
+pub mod pgsql;
+pub mod pseudonym;
+pub mod queries;
+pub mod sql;
+pub mod storage;
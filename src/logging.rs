@@ -0,0 +1,144 @@
+use chrono::{Local, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::str::FromStr;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+struct Logger {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+    format: Format,
+}
+
+//Collects the key value fields attached to a log record, eg. server and event type
+struct Fields(Vec<(String, json::JsonValue)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            number.into()
+        } else if let Some(number) = value.to_i64() {
+            number.into()
+        } else if let Some(number) = value.to_f64() {
+            number.into()
+        } else if let Some(boolean) = value.to_bool() {
+            boolean.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+impl Logger {
+    //The most specific module filter wins, otherwise the default level applies
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+
+        match self.format {
+            Format::Text => {
+                let mut line = format!("{} : {} : {}", Local::now().format("%H:%M:%S"), record.level(), record.args());
+                for (key, value) in &fields.0 {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+                match record.level() {
+                    Level::Error | Level::Warn => eprintln!("{}", line),
+                    _ => println!("{}", line),
+                }
+            }
+            Format::Json => {
+                let mut line = object! {
+                    timestamp: Utc::now().to_rfc3339(),
+                    level: record.level().as_str(),
+                    target: record.target(),
+                    message: record.args().to_string(),
+                };
+                for (key, value) in fields.0 {
+                    line[key] = value;
+                }
+                println!("{}", line.dump());
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+//Parse a filter such as "info,tf2_scan::sql=debug" into a default level and per module levels
+fn parse_filter(filter: &str) -> Result<(LevelFilter, Vec<(String, LevelFilter)>), String> {
+    let mut default = LevelFilter::Info;
+    let mut modules = Vec::new();
+
+    for directive in filter.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+        match directive.split_once('=') {
+            Some((module, level)) => {
+                let level = LevelFilter::from_str(level.trim()).map_err(|_| format!("Invalid log level ({})", level))?;
+                modules.push((module.trim().to_string(), level));
+            }
+            None => default = LevelFilter::from_str(directive).map_err(|_| format!("Invalid log level ({})", directive))?,
+        }
+    }
+
+    Ok((default, modules))
+}
+
+pub fn init(filter: &str, format: Format) -> Result<(), String> {
+    let (default, modules) = parse_filter(filter)?;
+    let max_level = modules.iter().map(|(_, level)| *level).chain([default]).max().unwrap();
+
+    log::set_boxed_logger(Box::new(Logger { default, modules, format })).map_err(|e| e.to_string())?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_default_and_module_levels() {
+        let (default, modules) = parse_filter("warn, tf2_scan::sql=debug,tf2_scan::status = trace").unwrap();
+        assert_eq!(default, LevelFilter::Warn);
+        assert_eq!(modules, vec![("tf2_scan::sql".to_string(), LevelFilter::Debug), ("tf2_scan::status".to_string(), LevelFilter::Trace)]);
+    }
+
+    #[test]
+    fn defaults_to_info() {
+        assert_eq!(parse_filter("").unwrap(), (LevelFilter::Info, Vec::new()));
+        assert_eq!(parse_filter("tf2_scan=off").unwrap(), (LevelFilter::Info, vec![("tf2_scan".to_string(), LevelFilter::Off)]));
+    }
+
+    #[test]
+    fn rejects_unknown_levels() {
+        assert!(parse_filter("loud").is_err());
+        assert!(parse_filter("info,tf2_scan=loud").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::{sync::Arc, thread};
+use tiny_http::{Header, Response, Server};
+
+pub struct Metrics {
+    registry: Registry,
+    pub scan_duration: Histogram,
+    pub db_duration: Histogram,
+    pub server_up: IntGaugeVec,
+    pub server_players: IntGaugeVec,
+    pub players: IntGauge,
+    pub servers_ok: IntGauge,
+    pub servers_failed: IntGauge,
+    pub events_written: IntCounter,
+    pub webhook_failures: IntCounter,
+    pub heartbeat_failures: IntCounter,
+    pub rows_pruned: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let buckets = vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+        let metrics = Metrics {
+            registry: Registry::new_custom(Some("tf2".to_string()), None).unwrap(),
+            scan_duration: Histogram::with_opts(HistogramOpts::new("scan_duration_seconds", "Time taken to query every server").buckets(buckets.clone())).unwrap(),
+            db_duration: Histogram::with_opts(HistogramOpts::new("db_duration_seconds", "Time taken to write a scan to the database").buckets(buckets)).unwrap(),
+            server_up: IntGaugeVec::new(Opts::new("server_up", "Whether the server answered the last info query"), &["server"]).unwrap(),
+            server_players: IntGaugeVec::new(Opts::new("server_players", "Players on the server at the last scan"), &["server"]).unwrap(),
+            players: IntGauge::new("players", "Players across all servers at the last scan").unwrap(),
+            servers_ok: IntGauge::new("servers_ok", "Servers that answered the last player query").unwrap(),
+            servers_failed: IntGauge::new("servers_failed", "Servers that failed the last player query").unwrap(),
+            events_written: IntCounter::new("events_written_total", "Events written to the database").unwrap(),
+            webhook_failures: IntCounter::new("webhook_failures_total", "Webhook alerts that could not be sent").unwrap(),
+            heartbeat_failures: IntCounter::new("heartbeat_failures_total", "Heartbeats that could not be sent").unwrap(),
+            rows_pruned: IntCounterVec::new(Opts::new("rows_pruned_total", "Rows deleted by the retention policy"), &["table"]).unwrap(),
+        };
+
+        metrics.registry.register(Box::new(metrics.scan_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.server_up.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.server_players.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.players.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.servers_ok.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.servers_failed.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.events_written.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.webhook_failures.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.heartbeat_failures.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.rows_pruned.clone())).unwrap();
+
+        metrics
+    }
+
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+//Serve the metrics in the prometheus text format on a background thread
+pub fn serve(metrics: Arc<Metrics>, address: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = Server::http(address)?;
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" => Response::from_string(metrics.encode()).with_header(content_type.clone()),
+                _ => Response::from_string("Not Found").with_status_code(404),
+            };
+            let _ = request.respond(response);
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,422 @@
+use crate::sql::{Player, PlayerEvent, PopulationSample, Server, ServerEvent, ServerSettings, Session};
+use crate::storage::{Result, Stats, Storage};
+use chrono::{DateTime, Utc};
+use postgres::{config::Host, types::ToSql, Client, NoTls, Statement};
+use std::collections::HashMap;
+
+//Same tables as up.sql and the sqlite migrations, times are utc unix milliseconds in both.
+//Applied in order and counted in schema_version, which is separate from sqlite's user_version.
+const MIGRATIONS: [&str; 3] = [
+    "CREATE TABLE servers (
+        server_id SERIAL PRIMARY KEY,
+        address TEXT UNIQUE NOT NULL
+    );
+    CREATE TABLE server_settings (
+        setting_id SERIAL PRIMARY KEY,
+        server_id INTEGER NOT NULL REFERENCES servers(server_id),
+        name TEXT NOT NULL,
+        max_players INTEGER NOT NULL,
+        current_map TEXT NOT NULL,
+        vac_status BOOLEAN NOT NULL,
+        has_password BOOLEAN NOT NULL,
+        game_version TEXT NOT NULL,
+        bots INTEGER NOT NULL,
+        created_at BIGINT NOT NULL
+    );
+    CREATE TABLE players (
+        player_id SERIAL PRIMARY KEY,
+        name TEXT UNIQUE NOT NULL
+    );
+    CREATE TABLE sessions (
+        session_id BIGSERIAL PRIMARY KEY,
+        server_id INTEGER NOT NULL REFERENCES servers(server_id),
+        player_id INTEGER NOT NULL REFERENCES players(player_id),
+        score INTEGER NOT NULL,
+        duration DOUBLE PRECISION NOT NULL,
+        joined_at BIGINT NOT NULL,
+        left_at BIGINT NOT NULL
+    );
+    CREATE TABLE server_events (
+        event_id BIGSERIAL PRIMARY KEY,
+        server_id INTEGER NOT NULL REFERENCES servers(server_id),
+        event_type TEXT NOT NULL,
+        event_data TEXT,
+        created_at BIGINT NOT NULL
+    );
+    CREATE TABLE player_events (
+        event_id BIGSERIAL PRIMARY KEY,
+        server_id INTEGER NOT NULL REFERENCES servers(server_id),
+        player_id INTEGER NOT NULL REFERENCES players(player_id),
+        event_type TEXT NOT NULL,
+        event_data TEXT,
+        created_at BIGINT NOT NULL
+    );
+    CREATE TABLE population_samples (
+        sample_id BIGSERIAL PRIMARY KEY,
+        server_id INTEGER NOT NULL REFERENCES servers(server_id),
+        players INTEGER NOT NULL,
+        bots INTEGER NOT NULL,
+        players_joined INTEGER NOT NULL,
+        players_left INTEGER NOT NULL,
+        created_at BIGINT NOT NULL
+    );
+    CREATE INDEX server_settings_server_created ON server_settings(server_id, created_at);
+    CREATE INDEX sessions_server_joined ON sessions(server_id, joined_at);
+    CREATE INDEX sessions_player ON sessions(player_id);
+    CREATE INDEX server_events_server_created ON server_events(server_id, created_at);
+    CREATE INDEX player_events_player_created ON player_events(player_id, created_at);
+    CREATE INDEX player_events_server_created ON player_events(server_id, created_at);
+    CREATE INDEX population_samples_server_created ON population_samples(server_id, created_at);",
+    //Bare map names and scores to json payloads, events without one hold NULL instead of an empty string
+    "UPDATE server_events SET event_data = '{\"map\":' || to_json(event_data)::TEXT || '}' WHERE event_type = 'setting change' AND event_data NOT LIKE '{%';
+    UPDATE server_events SET event_data = NULL WHERE event_type != 'setting change' AND event_data IS NOT NULL;
+    UPDATE player_events SET event_data = '{\"score\":' || event_data::BIGINT || '}' WHERE event_type = 'point change' AND event_data NOT LIKE '{%';
+    UPDATE player_events SET event_data = NULL WHERE event_type != 'point change' AND event_data IS NOT NULL;",
+    //Players stored as pseudonyms, names written before this table existed are marked by the scanner or db pseudonymise
+    "CREATE TABLE pseudonyms (
+        player_id INTEGER PRIMARY KEY REFERENCES players(player_id) ON DELETE CASCADE
+    );",
+];
+
+const TABLES: [&str; 8] = ["servers", "server_settings", "server_events", "players", "sessions", "player_events", "population_samples", "pseudonyms"];
+
+//Any number shared by every scanner, held while migrating so two starting at once don't both apply a migration
+const MIGRATION_LOCK: i64 = 0x7466_3273;
+
+pub struct PostgresStorage {
+    client: Client,
+    statements: HashMap<&'static str, Statement>,
+}
+
+//user@host/dbname of a connection string, without the password
+pub fn describe(url: &str) -> String {
+    match url.parse::<postgres::Config>() {
+        Ok(config) => {
+            let host = match config.get_hosts().first() {
+                Some(Host::Tcp(host)) => host.clone(),
+                Some(Host::Unix(path)) => path.display().to_string(),
+                None => "localhost".to_string(),
+            };
+            format!("postgres {}@{}/{}", config.get_user().unwrap_or(""), host, config.get_dbname().unwrap_or(""))
+        }
+        Err(_) => "postgres".to_string(),
+    }
+}
+
+//A session that refuses writes, also what tf2-analysis and tf2-api read through
+pub fn connect_read_only_client(url: &str) -> Result<Client> {
+    let mut client = Client::connect(url, NoTls)?;
+    client.batch_execute("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")?;
+    Ok(client)
+}
+
+//The primary key of each table the pruner deletes from
+fn id_column(table: &str) -> &'static str {
+    match table {
+        "server_settings" => "setting_id",
+        "sessions" => "session_id",
+        "population_samples" => "sample_id",
+        _ => "event_id",
+    }
+}
+
+impl PostgresStorage {
+    pub fn connect(url: &str) -> Result<PostgresStorage> {
+        Ok(PostgresStorage { client: Client::connect(url, NoTls)?, statements: HashMap::new() })
+    }
+
+    //For commands that only read
+    pub fn connect_read_only(url: &str) -> Result<PostgresStorage> {
+        Ok(PostgresStorage { client: connect_read_only_client(url)?, statements: HashMap::new() })
+    }
+
+    //Every write is one round trip, so the statements the scanner repeats are prepared once per connection
+    fn execute(&mut self, query: &'static str, params: &[&(dyn ToSql + Sync)]) -> Result<u64> {
+        let statement = match self.statements.get(query) {
+            Some(statement) => statement.clone(),
+            None => {
+                let statement = self.client.prepare(query)?;
+                self.statements.insert(query, statement.clone());
+                statement
+            }
+        };
+        Ok(self.client.execute(&statement, params)?)
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn migrate(&mut self) -> Result<usize> {
+        let mut tx = self.client.transaction()?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+        let exists: bool = tx.query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])?.get(0);
+        if !exists {
+            tx.batch_execute("CREATE TABLE schema_version (version INTEGER NOT NULL)")?;
+        }
+        let version = match tx.query_opt("SELECT version FROM schema_version", &[])? {
+            Some(row) => row.get::<_, i32>(0) as usize,
+            None => {
+                tx.execute("INSERT INTO schema_version (version) VALUES (0)", &[])?;
+                0
+            }
+        };
+        if version >= MIGRATIONS.len() {
+            return Ok(0);
+        }
+
+        for migration in &MIGRATIONS[version..] {
+            tx.batch_execute(migration)?;
+        }
+        tx.execute("UPDATE schema_version SET version = $1", &[&(MIGRATIONS.len() as i32)])?;
+        tx.commit()?;
+        Ok(MIGRATIONS.len() - version)
+    }
+
+    fn schema_version(&mut self) -> Result<(usize, usize)> {
+        let exists: bool = self.client.query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])?.get(0);
+        let version = match exists {
+            true => self.client.query_opt("SELECT version FROM schema_version", &[])?.map(|row| row.get::<_, i32>(0) as usize).unwrap_or(0),
+            false => 0,
+        };
+        Ok((version, MIGRATIONS.len()))
+    }
+
+    fn stats(&mut self) -> Result<Stats> {
+        let mut tables = Vec::new();
+        for table in TABLES {
+            let rows: i64 = self.client.query_one(&format!("SELECT COUNT(*) FROM {}", table), &[])?.get(0);
+            tables.push((table.to_string(), rows));
+        }
+        let size_bytes: i64 = self.client.query_one("SELECT pg_database_size(current_database())", &[])?.get(0);
+        Ok(Stats { tables, size_bytes, free_bytes: None })
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        Ok(self.client.batch_execute("BEGIN")?)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(self.client.batch_execute("COMMIT")?)
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        Ok(self.client.batch_execute("ROLLBACK")?)
+    }
+
+    fn insert_server(&mut self, address: &str) -> Result<()> {
+        self.execute("INSERT INTO servers (address) VALUES ($1) ON CONFLICT (address) DO NOTHING", &[&address])?;
+        Ok(())
+    }
+
+    fn get_server_by_addr(&mut self, address: &str) -> Result<Option<Server>> {
+        let row = self.client.query_opt("SELECT server_id, address FROM servers WHERE address = $1", &[&address])?;
+        Ok(row.map(|row| Server { server_id: row.get(0), address: row.get(1) }))
+    }
+
+    fn get_server_settings(&mut self, server_id: i32) -> Result<Option<ServerSettings>> {
+        let row = self.client.query_opt(
+            "SELECT setting_id, server_id, name, max_players, current_map, vac_status, has_password, game_version, bots, created_at
+            FROM server_settings WHERE server_id = $1 ORDER BY created_at DESC LIMIT 1",
+            &[&server_id],
+        )?;
+        Ok(row.map(|row| ServerSettings {
+            setting_id: row.get(0),
+            server_id: row.get(1),
+            name: row.get(2),
+            max_players: row.get(3),
+            current_map: row.get(4),
+            vac_status: row.get(5),
+            has_password: row.get(6),
+            game_version: row.get(7),
+            bots: row.get::<_, i32>(8) as u8,
+            created_at: DateTime::from_timestamp_millis(row.get(9)).unwrap(),
+        }))
+    }
+
+    fn insert_server_settings(&mut self, settings: &ServerSettings) -> Result<()> {
+        self.execute(
+            "INSERT INTO server_settings (server_id, name, max_players, current_map, vac_status, has_password, game_version, bots, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &settings.server_id,
+                &settings.name,
+                &settings.max_players,
+                &settings.current_map,
+                &settings.vac_status,
+                &settings.has_password,
+                &settings.game_version,
+                &(settings.bots as i32),
+                &settings.created_at.timestamp_millis(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_server_event(&mut self, event: &ServerEvent) -> Result<()> {
+        self.execute(
+            "INSERT INTO server_events (server_id, event_type, event_data, created_at) VALUES ($1, $2, $3, $4)",
+            &[&event.server_id, &event.kind.event_type(), &event.kind.event_data(), &event.created_at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    //One statement for the whole scan instead of a round trip per player
+    fn insert_players_batch(&mut self, players: &[Player], pseudonyms: bool) -> Result<()> {
+        let names: Vec<&str> = players.iter().map(|player| player.name.as_str()).collect();
+        self.execute("INSERT INTO players (name) SELECT unnest($1::TEXT[]) ON CONFLICT (name) DO NOTHING", &[&names])?;
+        if pseudonyms {
+            self.execute("INSERT INTO pseudonyms (player_id) SELECT player_id FROM players WHERE name = ANY($1) ON CONFLICT (player_id) DO NOTHING", &[&names])?;
+        }
+        Ok(())
+    }
+
+    fn insert_session(&mut self, name: &str, session: &Session) -> Result<()> {
+        self.execute(
+            "INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES ($1, (SELECT player_id FROM players WHERE name = $2), $3, $4, $5, $6)",
+            &[&session.server_id, &name, &session.score, &session.duration, &session.joined_at.timestamp_millis(), &session.left_at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    fn insert_player_event(&mut self, name: &str, event: &PlayerEvent) -> Result<()> {
+        self.execute(
+            "INSERT INTO player_events (server_id, player_id, event_type, event_data, created_at) VALUES ($1, (SELECT player_id FROM players WHERE name = $2), $3, $4, $5)",
+            &[&event.server_id, &name, &event.kind.event_type(), &event.kind.event_data(), &event.created_at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    fn insert_population_sample(&mut self, sample: &PopulationSample) -> Result<()> {
+        self.execute(
+            "INSERT INTO population_samples (server_id, players, bots, players_joined, players_left, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&sample.server_id, &sample.players, &sample.bots, &sample.players_joined, &sample.players_left, &sample.created_at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    fn delete_batch_before(&mut self, table: &str, column: &str, cutoff: DateTime<Utc>, limit: usize) -> Result<usize> {
+        //The latest settings of each server are what the scanner compares new info against, so they are always kept
+        let keep = match table {
+            "server_settings" => " AND setting_id NOT IN (SELECT MAX(setting_id) FROM server_settings GROUP BY server_id)",
+            _ => "",
+        };
+        let deleted = self.client.execute(
+            &format!("DELETE FROM {0} WHERE {3} IN (SELECT {3} FROM {0} WHERE {1} < $1{2} LIMIT $2)", table, column, keep, id_column(table)),
+            &[&cutoff.timestamp_millis(), &(limit as i64)],
+        )?;
+        Ok(deleted as usize)
+    }
+
+    fn delete_batch_unreferenced_players(&mut self, limit: usize) -> Result<usize> {
+        let deleted = self.client.execute(
+            "DELETE FROM players WHERE player_id IN (
+                SELECT player_id FROM players p
+                WHERE NOT EXISTS (SELECT 1 FROM sessions s WHERE s.player_id = p.player_id)
+                AND NOT EXISTS (SELECT 1 FROM player_events e WHERE e.player_id = p.player_id)
+                LIMIT $1)",
+            &[&(limit as i64)],
+        )?;
+        Ok(deleted as usize)
+    }
+
+    fn delete_player(&mut self, name: &str) -> Result<Option<(usize, usize)>> {
+        let mut tx = self.client.transaction()?;
+        let player_id: i32 = match tx.query_opt("SELECT player_id FROM players WHERE name = $1", &[&name])? {
+            Some(row) => row.get(0),
+            None => return Ok(None),
+        };
+        let sessions = tx.execute("DELETE FROM sessions WHERE player_id = $1", &[&player_id])?;
+        let events = tx.execute("DELETE FROM player_events WHERE player_id = $1", &[&player_id])?;
+        tx.execute("DELETE FROM players WHERE player_id = $1", &[&player_id])?;
+        tx.commit()?;
+        Ok(Some((sessions as usize, events as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::Database, queries, storage::tests};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SCHEMAS: AtomicUsize = AtomicUsize::new(0);
+
+    //Run with cargo test -- --ignored, every test gets an empty schema of its own in the database at TF2_TEST_POSTGRES_URL, dropped again afterwards
+    struct TestDatabase {
+        storage: PostgresStorage,
+        schema: String,
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            let _ = self.storage.client.batch_execute(&format!("DROP SCHEMA {} CASCADE", self.schema));
+        }
+    }
+
+    fn url() -> String {
+        std::env::var("TF2_TEST_POSTGRES_URL").expect("TF2_TEST_POSTGRES_URL is not set")
+    }
+
+    fn database() -> TestDatabase {
+        let schema = format!("tf2_test_{}_{}", std::process::id(), SCHEMAS.fetch_add(1, Ordering::Relaxed));
+        let mut storage = PostgresStorage::connect(&url()).unwrap();
+        storage.client.batch_execute(&format!("CREATE SCHEMA {0}; SET search_path TO {0}", schema)).unwrap();
+        TestDatabase { storage, schema }
+    }
+
+    fn run(case: fn(&mut dyn Storage)) {
+        case(&mut database().storage);
+    }
+
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_migrates_once() {
+        run(tests::migrates_once);
+    }
+
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_inserts() {
+        run(tests::inserts);
+    }
+
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_rolls_back() {
+        run(tests::rolls_back);
+    }
+
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_deletes_batches_before() {
+        run(tests::deletes_batches_before);
+    }
+
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_deletes_unreferenced_players() {
+        run(tests::deletes_unreferenced_players);
+    }
+
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_deletes_player() {
+        run(tests::deletes_player);
+    }
+
+    //The analysis and api queries read the same answers from both databases
+    #[test]
+    #[ignore = "needs a postgres database at TF2_TEST_POSTGRES_URL"]
+    fn postgres_answers_like_sqlite() {
+        let mut database = database();
+        database.storage.migrate().unwrap();
+        database.storage.client.batch_execute(queries::tests::FIXTURE).unwrap();
+        let mut client = connect_read_only_client(&url()).unwrap();
+        client.batch_execute(&format!("SET search_path TO {}", database.schema)).unwrap();
+        let mut postgres = Database::Postgres(client);
+
+        assert_eq!(queries::check_schema(&mut postgres), Ok(()));
+        let expected = queries::tests::answers(&mut queries::tests::fixture_database());
+        for (answer, expected) in queries::tests::answers(&mut postgres).iter().zip(&expected) {
+            assert_eq!(answer, expected);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::Write;
+
+//Bytes of the hmac kept, 128 bits is plenty to keep distinct names apart
+const LENGTH: usize = 16;
+
+//Turns player names into keyed hashes before they are stored, the same name and secret always give the same pseudonym
+#[derive(Clone)]
+pub struct Pseudonymiser {
+    key: Hmac<Sha256>,
+}
+
+impl Pseudonymiser {
+    pub fn new(secret: &str) -> Pseudonymiser {
+        Pseudonymiser { key: Hmac::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length") }
+    }
+
+    pub fn name(&self, name: &str) -> String {
+        let mut mac = self.key.clone();
+        mac.update(name.as_bytes());
+        mac.finalize().into_bytes()[..LENGTH].iter().fold(String::with_capacity(LENGTH * 2), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+}
+
+//The name as it goes into the database
+pub fn stored(pseudonymiser: Option<&Pseudonymiser>, name: &str) -> String {
+    match pseudonymiser {
+        Some(pseudonymiser) => pseudonymiser.name(name),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_rfc_4231_hmac() {
+        //Test case 2 of RFC 4231, cut to the first 16 bytes
+        let pseudonymiser = Pseudonymiser::new("Jefe");
+        assert_eq!(pseudonymiser.name("what do ya want for nothing?"), "5bdcc146bf60754e6a042426089575c7");
+    }
+
+    #[test]
+    fn truncates_to_32_hex_characters() {
+        let name = Pseudonymiser::new("secret").name("a player with a rather long name");
+        assert_eq!(name.len(), 32);
+        assert!(name.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+    }
+
+    #[test]
+    fn depends_on_the_secret() {
+        let name = Pseudonymiser::new("secret").name("player");
+        assert_eq!(Pseudonymiser::new("secret").name("player"), name);
+        assert_ne!(Pseudonymiser::new("other secret").name("player"), name);
+    }
+
+    #[test]
+    fn stores_names_as_is_without_a_pseudonymiser() {
+        assert_eq!(stored(None, "player"), "player");
+        assert_eq!(stored(Some(&Pseudonymiser::new("secret")), "player"), Pseudonymiser::new("secret").name("player"));
+    }
+}
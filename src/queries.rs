@@ -0,0 +1,947 @@
+use crate::{database::{Database, Param}, duration, sql, storage::Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+//Time range and server shared by every query, None leaves that side unbounded.
+//Times here and in the results are local wall clock times, the database stores utc unix milliseconds.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub server: Option<String>,
+}
+
+impl Filter {
+    pub fn since(&self) -> Option<i64> {
+        self.since.map(to_millis)
+    }
+
+    pub fn until(&self) -> Option<i64> {
+        self.until.map(to_millis)
+    }
+
+    //?1 since, ?2 until and ?3 server
+    pub fn params(&self) -> Vec<Param> {
+        vec![self.since().into(), self.until().into(), (&self.server).into()]
+    }
+}
+
+//Offset pagination for list queries
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Debug)]
+pub struct ServerSummary {
+    pub address: String,
+    pub name: Option<String>,
+    pub map: Option<String>,
+    pub sessions: i64,
+    pub unique_players: i64,
+    pub avg_session_minutes: f64,
+    pub player_hours: f64,
+    pub last_seen: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct PopulationSample {
+    pub address: String,
+    pub time: NaiveDateTime,
+    pub avg_players: f64,
+    pub unique_players: i64,
+}
+
+#[derive(Debug)]
+pub struct PeakHour {
+    pub hour: u32,
+    pub avg_players: f64,
+    pub peak_players: f64,
+}
+
+#[derive(Debug)]
+pub struct HeatmapCell {
+    pub address: String,
+    pub weekday: u32,
+    pub hour: u32,
+    pub avg_players: f64,
+    pub peak_players: f64,
+}
+
+#[derive(Debug)]
+pub struct MapPopularity {
+    pub map: String,
+    pub rounds: i64,
+    pub servers: i64,
+    pub hours: f64,
+    pub sessions: i64,
+}
+
+#[derive(Debug)]
+pub struct MapRetention {
+    pub address: String,
+    pub map: String,
+    pub rounds: i64,
+    pub avg_players_start: f64,
+    pub avg_players_end: f64,
+    pub joined: i64,
+    pub left: i64,
+    pub avg_minutes_per_player: f64,
+    pub churn_rate: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct Uptime {
+    pub address: String,
+    pub monitored_seconds: i64,
+    pub downtime_seconds: i64,
+    pub availability: f64,
+    pub outages: i64,
+    pub mtbf_seconds: Option<i64>,
+    pub mttr_seconds: Option<i64>,
+    pub longest_outage_seconds: i64,
+    pub longest_outage_start: Option<NaiveDateTime>,
+    pub outage_periods: Vec<(NaiveDateTime, i64)>,
+    pub first_scan: Option<NaiveDateTime>,
+    pub last_scan: Option<NaiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct ServerStatus {
+    pub address: String,
+    pub last_state: Option<String>,
+    pub last_scan: Option<DateTime<Utc>>,
+    pub name: Option<String>,
+    pub map: Option<String>,
+    pub max_players: Option<i64>,
+    pub has_password: Option<bool>,
+    pub vac_status: Option<bool>,
+    pub game_version: Option<String>,
+    pub bots: Option<i64>,
+    pub players: i64,
+}
+
+#[derive(Debug)]
+pub struct SettingsChange {
+    pub address: String,
+    pub time: String,
+    pub name: String,
+    pub map: String,
+    pub max_players: i64,
+    pub has_password: bool,
+    pub vac_status: bool,
+    pub game_version: String,
+    pub bots: i64,
+}
+
+#[derive(Debug)]
+pub struct MapRound {
+    pub address: String,
+    pub map: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub minutes: f64,
+    pub sessions: i64,
+}
+
+#[derive(Debug)]
+pub struct VersionChange {
+    pub address: String,
+    pub time: String,
+    pub previous_version: String,
+    pub version: String,
+}
+
+//Every settings row that changed the map starts a round, which lasts until the next one or the last scan of the server
+const MAP_ROUNDS: &str = "
+    changes AS (
+        SELECT server_id, current_map, created_at,
+            LAG(current_map) OVER (PARTITION BY server_id ORDER BY created_at) AS previous_map
+        FROM server_settings
+    ),
+    last_seen AS (
+        SELECT server_id, MAX(created_at) AS created_at FROM server_events GROUP BY server_id
+    ),
+    rounds AS (
+        SELECT c.server_id, c.current_map AS map, c.created_at AS started_at,
+            COALESCE(LEAD(c.created_at) OVER (PARTITION BY c.server_id ORDER BY c.created_at), l.created_at, c.created_at) AS ended_at
+        FROM changes c LEFT JOIN last_seen l ON l.server_id = c.server_id
+        WHERE c.previous_map IS NULL OR c.previous_map != c.current_map
+    )";
+
+pub fn server_summary(database: &mut Database, filter: &Filter) -> Result<Vec<ServerSummary>> {
+    database.query(
+        "SELECT s.address,
+            (SELECT name FROM server_settings ss WHERE ss.server_id = s.server_id ORDER BY created_at DESC LIMIT 1),
+            (SELECT current_map FROM server_settings ss WHERE ss.server_id = s.server_id ORDER BY created_at DESC LIMIT 1),
+            COUNT(se.session_id),
+            COUNT(DISTINCT se.player_id),
+            COALESCE(AVG(se.duration), 0) / 60.0,
+            COALESCE(SUM(se.duration), 0) / 3600.0,
+            (SELECT MAX(created_at) FROM server_events e WHERE e.server_id = s.server_id AND e.event_type = 'up')
+        FROM servers s
+        LEFT JOIN sessions se ON se.server_id = s.server_id AND (?1 IS NULL OR se.joined_at >= ?1) AND (?2 IS NULL OR se.joined_at < ?2)
+        WHERE (?3 IS NULL OR s.address = ?3)
+        GROUP BY s.server_id
+        ORDER BY s.address",
+        &filter.params(),
+        |row| {
+            Ok(ServerSummary {
+                address: row.get(0)?,
+                name: row.get(1)?,
+                map: row.get(2)?,
+                sessions: row.get(3)?,
+                unique_players: row.get(4)?,
+                avg_session_minutes: row.get(5)?,
+                player_hours: row.get(6)?,
+                last_seen: row.get::<Option<i64>>(7)?.map(format_millis),
+            })
+        },
+    )
+}
+
+//Average concurrent players per server in buckets of step seconds, built from the sessions overlapping each bucket.
+//Aggregate only scans write no sessions, buckets holding player counts sampled at those scans use the samples instead,
+//so a database that switched modes keeps both its history from sessions and the later samples.
+pub fn population(database: &mut Database, filter: &Filter, step: i64) -> Result<Vec<PopulationSample>> {
+    let sampled = has_population_samples(database)?;
+    let (mut first, mut last) = session_range(database)?;
+    if sampled {
+        let (sample_first, sample_last) = sample_range(database)?;
+        first = first.into_iter().chain(sample_first).min();
+        last = last.into_iter().chain(sample_last).max();
+    }
+    let (since, until) = match (filter.since.or(first), filter.until.or(last)) {
+        (Some(since), Some(until)) => (since, until),
+        _ => return Ok(Vec::new()),
+    };
+
+    //Align buckets to the step so hourly buckets start on the hour
+    let since = since - Duration::seconds(since.and_utc().timestamp().rem_euclid(step));
+
+    let sessions = session_population(database, filter, since, until, step)?;
+    if !sampled {
+        return Ok(sessions);
+    }
+    //Both queries return every server and bucket in the same order
+    let samples = sampled_population(database, filter, since, until, step)?;
+    Ok(sessions.into_iter().zip(samples).map(|(session, (sample, count))| if count > 0 { sample } else { session }).collect())
+}
+
+//Buckets of ?4 seconds from ?1 until ?2 as utc unix milliseconds
+const BUCKETS: &str = "
+    buckets(bucket_start, bucket_end) AS (
+        SELECT ?1, ?1 + ?4 * 1000
+        UNION ALL
+        SELECT bucket_end, bucket_end + ?4 * 1000 FROM buckets WHERE bucket_end < ?2
+    )";
+
+fn session_population(database: &mut Database, filter: &Filter, since: NaiveDateTime, until: NaiveDateTime, step: i64) -> Result<Vec<PopulationSample>> {
+    database.query(
+        &format!(
+            "WITH RECURSIVE {}
+            SELECT s.address, b.bucket_start,
+                CAST(COALESCE(SUM(
+                    CASE WHEN se.left_at > b.bucket_end THEN b.bucket_end ELSE se.left_at END
+                    - CASE WHEN se.joined_at < b.bucket_start THEN b.bucket_start ELSE se.joined_at END
+                ), 0) AS DOUBLE PRECISION) / 1000.0 / ?4,
+                COUNT(DISTINCT se.player_id)
+            FROM buckets b
+            CROSS JOIN servers s
+            LEFT JOIN sessions se ON se.server_id = s.server_id AND se.joined_at < b.bucket_end AND se.left_at > b.bucket_start
+            WHERE (?3 IS NULL OR s.address = ?3)
+            GROUP BY s.server_id, b.bucket_start
+            ORDER BY b.bucket_start, s.address",
+            BUCKETS
+        ),
+        &[to_millis(since).into(), to_millis(until).into(), (&filter.server).into(), step.into()],
+        |row| {
+            Ok(PopulationSample {
+                address: row.get(0)?,
+                time: from_millis(row.get(1)?),
+                avg_players: row.get(2)?,
+                unique_players: row.get(3)?,
+            })
+        },
+    )
+}
+
+//Unique players of a bucket can't be known without names, the peak sampled count is the closest lower bound.
+//Each bucket comes with the number of samples in it.
+fn sampled_population(database: &mut Database, filter: &Filter, since: NaiveDateTime, until: NaiveDateTime, step: i64) -> Result<Vec<(PopulationSample, i64)>> {
+    database.query(
+        &format!(
+            "WITH RECURSIVE {}
+            SELECT s.address, b.bucket_start,
+                CAST(COALESCE(AVG(p.players), 0) AS DOUBLE PRECISION),
+                CAST(COALESCE(MAX(p.players), 0) AS BIGINT),
+                COUNT(p.sample_id)
+            FROM buckets b
+            CROSS JOIN servers s
+            LEFT JOIN population_samples p ON p.server_id = s.server_id AND p.created_at >= b.bucket_start AND p.created_at < b.bucket_end
+            WHERE (?3 IS NULL OR s.address = ?3)
+            GROUP BY s.server_id, b.bucket_start
+            ORDER BY b.bucket_start, s.address",
+            BUCKETS
+        ),
+        &[to_millis(since).into(), to_millis(until).into(), (&filter.server).into(), step.into()],
+        |row| {
+            Ok((
+                PopulationSample {
+                    address: row.get(0)?,
+                    time: from_millis(row.get(1)?),
+                    avg_players: row.get(2)?,
+                    unique_players: row.get(3)?,
+                },
+                row.get(4)?,
+            ))
+        },
+    )
+}
+
+//Average and busiest hourly population across all servers for each hour of the day
+pub fn peak_hours(database: &mut Database, filter: &Filter) -> Result<Vec<PeakHour>> {
+    let mut totals: Vec<(NaiveDateTime, f64)> = Vec::new();
+    for sample in population(database, filter, 3600)? {
+        match totals.last_mut() {
+            Some((time, players)) if *time == sample.time => *players += sample.avg_players,
+            _ => totals.push((sample.time, sample.avg_players)),
+        }
+    }
+
+    Ok((0..24)
+        .map(|hour| {
+            let players: Vec<f64> = totals.iter().filter(|(time, _)| time.hour() == hour).map(|(_, players)| *players).collect();
+            PeakHour {
+                hour,
+                avg_players: match players.len() {
+                    0 => 0.0,
+                    count => players.iter().sum::<f64>() / count as f64,
+                },
+                peak_players: players.iter().cloned().fold(0.0, f64::max),
+            }
+        })
+        .collect())
+}
+
+//Average and peak hourly population of every server for each hour of the week, weekday 0 is Monday
+pub fn heatmap(database: &mut Database, filter: &Filter) -> Result<Vec<HeatmapCell>> {
+    let mut cells: Vec<(String, Vec<Vec<f64>>)> = Vec::new();
+    for sample in population(database, filter, 3600)? {
+        let index = match cells.iter().position(|(address, _)| *address == sample.address) {
+            Some(index) => index,
+            None => {
+                cells.push((sample.address.clone(), vec![Vec::new(); 7 * 24]));
+                cells.len() - 1
+            }
+        };
+        let hour_of_week = sample.time.weekday().num_days_from_monday() * 24 + sample.time.hour();
+        cells[index].1[hour_of_week as usize].push(sample.avg_players);
+    }
+    cells.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(cells
+        .into_iter()
+        .flat_map(|(address, hours)| {
+            hours.into_iter().enumerate().map(move |(hour_of_week, players)| HeatmapCell {
+                address: address.clone(),
+                weekday: hour_of_week as u32 / 24,
+                hour: hour_of_week as u32 % 24,
+                avg_players: match players.len() {
+                    0 => 0.0,
+                    count => players.iter().sum::<f64>() / count as f64,
+                },
+                peak_players: players.iter().cloned().fold(0.0, f64::max),
+            })
+        })
+        .collect())
+}
+
+pub fn map_popularity(database: &mut Database, filter: &Filter) -> Result<Vec<MapPopularity>> {
+    database.query(
+        &format!(
+            "WITH {}
+            SELECT r.map, COUNT(*), COUNT(DISTINCT r.server_id),
+                CAST(SUM(r.ended_at - r.started_at) AS DOUBLE PRECISION) / 3600000.0,
+                CAST(SUM((SELECT COUNT(*) FROM sessions se WHERE se.server_id = r.server_id AND se.joined_at >= r.started_at AND se.joined_at < r.ended_at)) AS BIGINT)
+            FROM rounds r JOIN servers s ON s.server_id = r.server_id
+            WHERE (?1 IS NULL OR r.started_at >= ?1) AND (?2 IS NULL OR r.started_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+            GROUP BY r.map
+            ORDER BY 4 DESC, r.map",
+            MAP_ROUNDS
+        ),
+        &filter.params(),
+        |row| {
+            Ok(MapPopularity {
+                map: row.get(0)?,
+                rounds: row.get(1)?,
+                servers: row.get(2)?,
+                hours: row.get(3)?,
+                sessions: row.get(4)?,
+            })
+        },
+    )
+}
+
+//Players present at the start and end of each map, how long they stayed and how many left before it ended, per server and map.
+//Minutes per player is the session time inside the rounds divided by the players who were there at the start or joined,
+//churn is the share of those players that left before the map ended.
+pub fn map_retention(database: &mut Database, filter: &Filter) -> Result<Vec<MapRetention>> {
+    database.query(
+        &format!(
+            "WITH {},
+            round_stats AS (
+                SELECT r.server_id, s.address, r.map,
+                    SUM(CASE WHEN se.joined_at <= r.started_at THEN 1 ELSE 0 END) AS at_start,
+                    SUM(CASE WHEN se.left_at >= r.ended_at THEN 1 ELSE 0 END) AS at_end,
+                    SUM(CASE WHEN se.joined_at > r.started_at THEN 1 ELSE 0 END) AS joined,
+                    SUM(CASE WHEN se.left_at < r.ended_at THEN 1 ELSE 0 END) AS left_early,
+                    CAST(COALESCE(SUM(
+                        CASE WHEN se.left_at > r.ended_at THEN r.ended_at ELSE se.left_at END
+                        - CASE WHEN se.joined_at < r.started_at THEN r.started_at ELSE se.joined_at END
+                    ), 0) AS DOUBLE PRECISION) / 1000.0 AS seconds_on_map
+                FROM rounds r
+                JOIN servers s ON s.server_id = r.server_id
+                LEFT JOIN sessions se ON se.server_id = r.server_id AND se.joined_at < r.ended_at AND se.left_at > r.started_at
+                WHERE (?1 IS NULL OR r.started_at >= ?1) AND (?2 IS NULL OR r.started_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+                GROUP BY r.server_id, s.address, r.map, r.started_at
+            )
+            SELECT address, map, COUNT(*), CAST(AVG(at_start) AS DOUBLE PRECISION), CAST(AVG(at_end) AS DOUBLE PRECISION), CAST(SUM(joined) AS BIGINT), CAST(SUM(left_early) AS BIGINT),
+                CASE WHEN SUM(at_start) + SUM(joined) > 0 THEN SUM(seconds_on_map) / 60.0 / (SUM(at_start) + SUM(joined)) ELSE 0 END,
+                CASE WHEN SUM(at_start) + SUM(joined) > 0 THEN CAST(SUM(left_early) AS DOUBLE PRECISION) / (SUM(at_start) + SUM(joined)) ELSE 0 END
+            FROM round_stats
+            GROUP BY server_id, address, map
+            ORDER BY address, COUNT(*) DESC, map",
+            MAP_ROUNDS
+        ),
+        &filter.params(),
+        |row| {
+            Ok(MapRetention {
+                address: row.get(0)?,
+                map: row.get(1)?,
+                rounds: row.get(2)?,
+                avg_players_start: row.get(3)?,
+                avg_players_end: row.get(4)?,
+                joined: row.get(5)?,
+                left: row.get(6)?,
+                avg_minutes_per_player: row.get(7)?,
+                churn_rate: row.get(8)?,
+            })
+        },
+    )
+}
+
+//Availability and outages of every server from its up/down scans.
+//Each scan holds its state until the next scan, gaps longer than max_gap seconds (scanner not running) count as unmonitored
+//and end any outage, a server down on both sides of a gap has two outages.
+pub fn uptime(database: &mut Database, filter: &Filter, max_gap: i64) -> Result<Vec<Uptime>> {
+    let mut reports: Vec<Uptime> = Vec::new();
+    let mut in_outage = false;
+
+    database.for_each(
+        "SELECT s.address, e.event_type, e.created_at / 1000,
+            LEAD(e.created_at) OVER (PARTITION BY e.server_id ORDER BY e.created_at, e.event_id) / 1000
+        FROM server_events e JOIN servers s ON s.server_id = e.server_id
+        WHERE e.event_type IN ('up', 'down') AND (?1 IS NULL OR e.created_at >= ?1) AND (?2 IS NULL OR e.created_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+        ORDER BY s.address, e.created_at, e.event_id",
+        &filter.params(),
+        |row| {
+            let address: String = row.get(0)?;
+            let event_type: String = row.get(1)?;
+            let time: i64 = row.get(2)?;
+            let next: Option<i64> = row.get(3)?;
+
+            if reports.last().map(|report| report.address != address).unwrap_or(true) {
+                reports.push(Uptime { address, first_scan: from_timestamp(time), ..Default::default() });
+                in_outage = false;
+            }
+            let report = reports.last_mut().unwrap();
+            report.last_scan = from_timestamp(time);
+
+            let interval = match next {
+                Some(next) if next - time <= max_gap => Some(next - time),
+                _ => None,
+            };
+            report.monitored_seconds += interval.unwrap_or(0);
+
+            match event_type.as_str() {
+                "down" => {
+                    report.downtime_seconds += interval.unwrap_or(0);
+                    if !in_outage {
+                        report.outage_periods.push((from_timestamp(time).unwrap(), 0));
+                    }
+                    report.outage_periods.last_mut().unwrap().1 += interval.unwrap_or(0);
+                    in_outage = interval.is_some();
+                }
+                _ => in_outage = false,
+            }
+            Ok(())
+        },
+    )?;
+
+    for report in &mut reports {
+        report.outages = report.outage_periods.len() as i64;
+        if let Some((start, length)) = report.outage_periods.iter().max_by_key(|(_, length)| *length) {
+            report.longest_outage_start = Some(*start);
+            report.longest_outage_seconds = *length;
+        }
+
+        let uptime_seconds = report.monitored_seconds - report.downtime_seconds;
+        report.availability = match report.monitored_seconds {
+            0 => 0.0,
+            monitored => uptime_seconds as f64 * 100.0 / monitored as f64,
+        };
+        if report.outages > 0 {
+            report.mtbf_seconds = Some(uptime_seconds / report.outages);
+            report.mttr_seconds = Some(report.downtime_seconds / report.outages);
+        }
+    }
+
+    Ok(reports)
+}
+
+//Latest up/down scan and settings of every server, the scan time stays utc so it can be compared without daylight saving getting in the way.
+//Players are those whose last join/leave event since the server last went down is a join.
+pub fn server_status(database: &mut Database, filter: &Filter) -> Result<Vec<ServerStatus>> {
+    database.query(
+        "SELECT s.address, e.event_type, e.created_at,
+            ss.name, ss.current_map, CAST(ss.max_players AS BIGINT), ss.has_password, ss.vac_status, ss.game_version, CAST(ss.bots AS BIGINT),
+            (SELECT COUNT(*) FROM (
+                SELECT MAX(pe.event_id) AS last_event, MAX(CASE WHEN pe.event_type = 'join' THEN pe.event_id END) AS last_join
+                FROM player_events pe
+                WHERE pe.server_id = s.server_id AND pe.event_type IN ('join', 'leave')
+                    AND pe.created_at >= COALESCE((SELECT MAX(created_at) FROM server_events WHERE server_id = s.server_id AND event_type = 'down'), 0)
+                GROUP BY pe.player_id
+            ) latest WHERE last_event = last_join)
+        FROM servers s
+        LEFT JOIN server_events e ON e.event_id = (SELECT MAX(event_id) FROM server_events WHERE server_id = s.server_id AND event_type IN ('up', 'down'))
+        LEFT JOIN server_settings ss ON ss.setting_id = (SELECT MAX(setting_id) FROM server_settings WHERE server_id = s.server_id)
+        WHERE (?1 IS NULL OR s.address = ?1)
+        ORDER BY s.address",
+        &[(&filter.server).into()],
+        |row| {
+            Ok(ServerStatus {
+                address: row.get(0)?,
+                last_state: row.get(1)?,
+                last_scan: row.get::<Option<i64>>(2)?.and_then(DateTime::from_timestamp_millis),
+                name: row.get(3)?,
+                map: row.get(4)?,
+                max_players: row.get(5)?,
+                has_password: row.get(6)?,
+                vac_status: row.get(7)?,
+                game_version: row.get(8)?,
+                bots: row.get(9)?,
+                players: row.get(10)?,
+            })
+        },
+    )
+}
+
+//Settings rows newest first
+pub fn settings_history(database: &mut Database, filter: &Filter, page: Page) -> Result<Vec<SettingsChange>> {
+    let mut params = filter.params();
+    params.extend([page.limit.into(), page.offset.into()]);
+    database.query(
+        "SELECT s.address, ss.created_at, ss.name, ss.current_map, CAST(ss.max_players AS BIGINT), ss.has_password, ss.vac_status, ss.game_version, CAST(ss.bots AS BIGINT)
+        FROM server_settings ss JOIN servers s ON s.server_id = ss.server_id
+        WHERE (?1 IS NULL OR ss.created_at >= ?1) AND (?2 IS NULL OR ss.created_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+        ORDER BY ss.created_at DESC, ss.setting_id DESC
+        LIMIT ?4 OFFSET ?5",
+        &params,
+        |row| {
+            Ok(SettingsChange {
+                address: row.get(0)?,
+                time: format_millis(row.get(1)?),
+                name: row.get(2)?,
+                map: row.get(3)?,
+                max_players: row.get(4)?,
+                has_password: row.get(5)?,
+                vac_status: row.get(6)?,
+                game_version: row.get(7)?,
+                bots: row.get(8)?,
+            })
+        },
+    )
+}
+
+//Map rounds newest first, with the sessions that started during each
+pub fn map_rounds(database: &mut Database, filter: &Filter, page: Page) -> Result<Vec<MapRound>> {
+    let mut params = filter.params();
+    params.extend([page.limit.into(), page.offset.into()]);
+    database.query(
+        &format!(
+            "WITH {}
+            SELECT s.address, r.map, r.started_at, r.ended_at,
+                CAST(r.ended_at - r.started_at AS DOUBLE PRECISION) / 60000.0,
+                (SELECT COUNT(*) FROM sessions se WHERE se.server_id = r.server_id AND se.joined_at >= r.started_at AND se.joined_at < r.ended_at)
+            FROM rounds r JOIN servers s ON s.server_id = r.server_id
+            WHERE (?1 IS NULL OR r.started_at >= ?1) AND (?2 IS NULL OR r.started_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+            ORDER BY r.started_at DESC, s.address
+            LIMIT ?4 OFFSET ?5",
+            MAP_ROUNDS
+        ),
+        &params,
+        |row| {
+            Ok(MapRound {
+                address: row.get(0)?,
+                map: row.get(1)?,
+                started_at: format_millis(row.get(2)?),
+                ended_at: format_millis(row.get(3)?),
+                minutes: row.get(4)?,
+                sessions: row.get(5)?,
+            })
+        },
+    )
+}
+
+//Every settings row where a server reported a different game_version than its previous one
+pub fn version_changes(database: &mut Database, filter: &Filter) -> Result<Vec<VersionChange>> {
+    database.query(
+        "WITH changes AS (
+            SELECT server_id, game_version, created_at,
+                LAG(game_version) OVER (PARTITION BY server_id ORDER BY created_at) AS previous_version
+            FROM server_settings
+        )
+        SELECT s.address, c.created_at, c.previous_version, c.game_version
+        FROM changes c JOIN servers s ON s.server_id = c.server_id
+        WHERE c.previous_version != c.game_version
+            AND (?1 IS NULL OR c.created_at >= ?1) AND (?2 IS NULL OR c.created_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+        ORDER BY c.created_at, s.address",
+        &filter.params(),
+        |row| {
+            Ok(VersionChange {
+                address: row.get(0)?,
+                time: format_millis(row.get(1)?),
+                previous_version: row.get(2)?,
+                version: row.get(3)?,
+            })
+        },
+    )
+}
+
+//Session lengths bucketed by bucket seconds, the last bucket also holds every longer session
+pub fn session_lengths(database: &mut Database, filter: &Filter, bucket: i64, buckets: i64) -> Result<Vec<(i64, i64)>> {
+    //Sqlite casts toward zero, postgres rounds
+    let index = match database {
+        Database::Sqlite(_) => "CAST(se.duration / ?4 AS INTEGER)",
+        Database::Postgres(_) => "CAST(FLOOR(se.duration / ?4) AS BIGINT)",
+    };
+    let mut params = filter.params();
+    params.extend([bucket.into(), buckets.into()]);
+    let counts = database.query(
+        &format!(
+            "SELECT CASE WHEN {0} > ?5 - 1 THEN ?5 - 1 ELSE {0} END AS bucket, COUNT(*)
+            FROM sessions se JOIN servers s ON s.server_id = se.server_id
+            WHERE (?1 IS NULL OR se.joined_at >= ?1) AND (?2 IS NULL OR se.joined_at < ?2) AND (?3 IS NULL OR s.address = ?3)
+            GROUP BY bucket",
+            index
+        ),
+        &params,
+        |row| Ok((row.get::<i64>(0)?, row.get::<i64>(1)?)),
+    )?;
+
+    Ok((0..buckets)
+        .map(|index| (index * bucket, counts.iter().find(|(bucket, _)| *bucket == index).map(|(_, count)| *count).unwrap_or(0)))
+        .collect())
+}
+
+fn from_timestamp(timestamp: i64) -> Option<NaiveDateTime> {
+    Local.timestamp_opt(timestamp, 0).earliest().map(|time| time.naive_local())
+}
+
+//Tables every query reads, population_samples is optional as older databases don't have it
+const TABLES: [&str; 6] = ["servers", "server_settings", "players", "sessions", "server_events", "player_events"];
+
+//Before schema version 3 times were local time strings, those databases have to be migrated by the scanner before they can be read.
+//A database created from up.sql or players-empty.db can also be at version 0, so it is the stored times that decide.
+pub fn check_schema(database: &mut Database) -> std::result::Result<(), String> {
+    for table in TABLES {
+        if !database.has_table(table).map_err(|e| e.to_string())? {
+            return Err(match database {
+                Database::Sqlite(_) => format!("Database has no {} table, start the scanner on a copy of db-tools/players-empty.db", table),
+                Database::Postgres(_) => format!("Database has no {} table, start the scanner on it first so it creates them", table),
+            });
+        }
+    }
+
+    //Postgres databases never held local times
+    if let Database::Sqlite(conn) = database {
+        let (version, _) = sql::schema_version(conn).map_err(|e| e.to_string())?;
+        if version <= sql::LOCAL_TIMES && sql::has_local_times(conn).map_err(|e| e.to_string())? {
+            return Err("Database is from an older version, run tf2-scan db migrate on it first".to_string());
+        }
+    }
+    Ok(())
+}
+
+//Older databases don't have the table until the scanner has migrated them
+fn has_population_samples(database: &mut Database) -> Result<bool> {
+    match database.has_table("population_samples")? {
+        true => database.query_one("SELECT EXISTS (SELECT 1 FROM population_samples)", &[], |row| row.get(0)),
+        false => Ok(false),
+    }
+}
+
+fn sample_range(database: &mut Database) -> Result<(Option<NaiveDateTime>, Option<NaiveDateTime>)> {
+    database.query_one("SELECT MIN(created_at), MAX(created_at) FROM population_samples", &[], |row| {
+        Ok((row.get::<Option<i64>>(0)?.map(from_millis), row.get::<Option<i64>>(1)?.map(from_millis)))
+    })
+}
+
+fn session_range(database: &mut Database) -> Result<(Option<NaiveDateTime>, Option<NaiveDateTime>)> {
+    database.query_one("SELECT MIN(joined_at), MAX(left_at) FROM sessions", &[], |row| {
+        Ok((row.get::<Option<i64>>(0)?.map(from_millis), row.get::<Option<i64>>(1)?.map(from_millis)))
+    })
+}
+
+//Local wall clock time as stored utc unix milliseconds, a time skipped by a daylight saving change is read with the offset from before it
+pub fn to_millis(time: NaiveDateTime) -> i64 {
+    match Local.from_local_datetime(&time).earliest() {
+        Some(time) => time.timestamp_millis(),
+        None => to_millis(time - Duration::hours(1)) + 3600000,
+    }
+}
+
+pub fn from_millis(millis: i64) -> NaiveDateTime {
+    Local.timestamp_millis_opt(millis).unwrap().naive_local()
+}
+
+pub fn format_millis(millis: i64) -> String {
+    from_millis(millis).format(TIME_FORMAT).to_string()
+}
+
+//Accepts a duration before now such as "30m", "12h", "7d" or "2w", or a date "2023-11-01" / "2023-11-01 18:00:00"
+pub fn parse_time(input: &str) -> std::result::Result<NaiveDateTime, String> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(input, TIME_FORMAT) {
+        return Ok(time);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+    }
+    duration::parse_duration(input).map(|duration| Local::now().naive_local() - Duration::seconds(duration))
+}
+
+//The sqlite answers to the fixture are what the postgres test in pgsql compares against
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rusqlite::{params, Connection};
+
+    //Two servers from 2024-01-10 12:00 UTC, a version and a map change, an outage, sessions and samples.
+    //Plain sql both databases run the same, ids come out the same as they are inserted in order.
+    pub const FIXTURE: &str = "
+        INSERT INTO servers (address) VALUES ('127.0.0.1:27015'), ('127.0.0.1:27016');
+        INSERT INTO players (name) VALUES ('a'), ('b'), ('c');
+        INSERT INTO server_settings (server_id, name, max_players, current_map, vac_status, has_password, game_version, bots, created_at) VALUES
+            (1, 'one', 24, 'pl_upward', TRUE, FALSE, '100', 0, 1704888000000),
+            (2, 'two', 32, 'ctf_2fort', FALSE, TRUE, '100', 0, 1704888600000),
+            (1, 'one', 24, 'pl_upward', TRUE, FALSE, '101', 0, 1704889800000),
+            (1, 'one', 24, 'cp_dustbowl', TRUE, FALSE, '101', 2, 1704891600000);
+        INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES
+            (1, 1, 5, 3600.5, 1704888000000, 1704891600000),
+            (1, 2, 3, 3600, 1704889800000, 1704893400000),
+            (2, 3, 0, 600, 1704888600000, 1704889200000);
+        INSERT INTO server_events (server_id, event_type, event_data, created_at) VALUES
+            (1, 'up', NULL, 1704888000000),
+            (1, 'up', NULL, 1704888300000),
+            (1, 'down', NULL, 1704888600000),
+            (1, 'down', NULL, 1704888900000),
+            (1, 'up', NULL, 1704889200000),
+            (1, 'setting change', '{\"map\":\"cp_dustbowl\"}', 1704891600000),
+            (1, 'up', NULL, 1704891600000),
+            (1, 'up', NULL, 1704895200000),
+            (2, 'up', NULL, 1704888600000),
+            (2, 'up', NULL, 1704888900000);
+        INSERT INTO population_samples (server_id, players, bots, players_joined, players_left, created_at) VALUES
+            (1, 2, 0, 1, 0, 1704891600000),
+            (1, 1, 0, 0, 1, 1704892800000),
+            (1, 0, 0, 0, 1, 1704894000000);";
+
+    //Every query over the fixture, unfiltered and narrowed to part of it
+    pub fn answers(database: &mut Database) -> Vec<String> {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let narrowed = Filter { since: Some(start + Duration::minutes(20)), until: Some(start + Duration::minutes(100)), server: Some("127.0.0.1:27015".to_string()) };
+        let page = Page { limit: 2, offset: 1 };
+        let mut answers = Vec::new();
+        for filter in [Filter::default(), narrowed] {
+            answers.push(format!("{:?}", server_summary(database, &filter).unwrap()));
+            answers.push(format!("{:?}", population(database, &filter, 1800).unwrap()));
+            answers.push(format!("{:?}", peak_hours(database, &filter).unwrap()));
+            answers.push(format!("{:?}", heatmap(database, &filter).unwrap()));
+            answers.push(format!("{:?}", map_popularity(database, &filter).unwrap()));
+            answers.push(format!("{:?}", map_retention(database, &filter).unwrap()));
+            answers.push(format!("{:?}", uptime(database, &filter, 600).unwrap()));
+            answers.push(format!("{:?}", server_status(database, &filter).unwrap()));
+            answers.push(format!("{:?}", settings_history(database, &filter, page).unwrap()));
+            answers.push(format!("{:?}", map_rounds(database, &filter, page).unwrap()));
+            answers.push(format!("{:?}", version_changes(database, &filter).unwrap()));
+            answers.push(format!("{:?}", session_lengths(database, &filter, 900, 4).unwrap()));
+        }
+        answers
+    }
+
+    pub fn fixture_database() -> Database {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        conn.execute_batch(FIXTURE).unwrap();
+        Database::Sqlite(conn)
+    }
+
+    #[test]
+    fn parses_dates_and_times() {
+        assert_eq!(parse_time("2023-11-01 18:30:05"), Ok(NaiveDate::from_ymd_opt(2023, 11, 1).unwrap().and_hms_opt(18, 30, 5).unwrap()));
+        assert_eq!(parse_time("2023-11-01"), Ok(NaiveDate::from_ymd_opt(2023, 11, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn parses_durations_before_now() {
+        let before = Local::now().naive_local();
+        let time = parse_time("2h").unwrap();
+        let after = Local::now().naive_local();
+        assert!(time >= before - Duration::hours(2) && time <= after - Duration::hours(2));
+    }
+
+    //An hour of sessions, then an hour of aggregate only scans
+    fn switched_database() -> (Connection, NaiveDateTime) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        let start = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let at = |minutes: i64| to_millis(start + Duration::minutes(minutes));
+        conn.execute_batch("INSERT INTO servers (address) VALUES ('127.0.0.1:27015'); INSERT INTO players (name) VALUES ('a'), ('b');").unwrap();
+        for player_id in [1, 2] {
+            conn.execute(
+                "INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES (1, ?1, 0, 3600, ?2, ?3)",
+                params![player_id, at(0), at(60)],
+            ).unwrap();
+        }
+        for minutes in [60, 80, 100] {
+            conn.execute(
+                "INSERT INTO population_samples (server_id, players, bots, players_joined, players_left, created_at) VALUES (1, 6, 0, 0, 0, ?1)",
+                params![at(minutes)],
+            ).unwrap();
+        }
+        (conn, start)
+    }
+
+    #[test]
+    fn population_uses_samples_only_where_they_exist() {
+        let (conn, start) = switched_database();
+        let population = population(&mut Database::Sqlite(conn), &Filter::default(), 3600).unwrap();
+        let hours: Vec<_> = population.iter().map(|sample| (sample.time, sample.avg_players, sample.unique_players)).collect();
+        assert_eq!(hours, vec![(start, 2.0, 2), (start + Duration::hours(1), 6.0, 6)]);
+    }
+
+    #[test]
+    fn population_without_samples_uses_sessions() {
+        let (conn, start) = switched_database();
+        conn.execute("DELETE FROM population_samples", []).unwrap();
+        let population = population(&mut Database::Sqlite(conn), &Filter::default(), 3600).unwrap();
+        let hours: Vec<_> = population.iter().map(|sample| (sample.time, sample.avg_players, sample.unique_players)).collect();
+        assert_eq!(hours, vec![(start, 2.0, 2)]);
+    }
+
+    #[test]
+    fn checks_schema_by_the_stored_times() {
+        let (conn, _) = switched_database();
+        let mut database = Database::Sqlite(conn);
+        assert_eq!(check_schema(&mut database), Ok(()));
+
+        let Database::Sqlite(conn) = &database else { unreachable!() };
+        conn.execute("INSERT INTO server_events (server_id, event_type, created_at) VALUES (1, 'up', '2023-11-01 18:00:00')", []).unwrap();
+        assert!(check_schema(&mut database).is_err());
+        let Database::Sqlite(conn) = &database else { unreachable!() };
+        conn.pragma_update(None, "user_version", sql::LOCAL_TIMES + 1).unwrap();
+        assert_eq!(check_schema(&mut database), Ok(()));
+
+        assert!(check_schema(&mut Database::Sqlite(Connection::open_in_memory().unwrap())).is_err());
+    }
+
+    //One server with an up or down scan at each of the given seconds
+    fn scanned_database(scans: &[(i64, &str)]) -> (Connection, NaiveDateTime) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        conn.execute("INSERT INTO servers (address) VALUES ('127.0.0.1:27015')", []).unwrap();
+        let start = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        for (seconds, event_type) in scans {
+            conn.execute(
+                "INSERT INTO server_events (server_id, event_type, created_at) VALUES (1, ?1, ?2)",
+                params![event_type, to_millis(start + Duration::seconds(*seconds))],
+            ).unwrap();
+        }
+        (conn, start)
+    }
+
+    #[test]
+    fn uptime_counts_outages_between_scans() {
+        let (conn, start) = scanned_database(&[(0, "up"), (60, "up"), (120, "down"), (180, "down"), (240, "up")]);
+        let report = uptime(&mut Database::Sqlite(conn), &Filter::default(), 120).unwrap().remove(0);
+        assert_eq!((report.monitored_seconds, report.downtime_seconds, report.availability), (240, 120, 50.0));
+        assert_eq!((report.outages, report.mtbf_seconds, report.mttr_seconds), (1, Some(120), Some(120)));
+        assert_eq!((report.longest_outage_start, report.longest_outage_seconds), (Some(start + Duration::seconds(120)), 120));
+        assert_eq!((report.first_scan, report.last_scan), (Some(start), Some(start + Duration::seconds(240))));
+    }
+
+    #[test]
+    fn uptime_ends_an_outage_at_a_gap() {
+        let (conn, start) = scanned_database(&[(0, "down"), (60, "down"), (1000, "down"), (1060, "down"), (1120, "up")]);
+        let report = uptime(&mut Database::Sqlite(conn), &Filter::default(), 120).unwrap().remove(0);
+        assert_eq!((report.monitored_seconds, report.downtime_seconds), (180, 180));
+        assert_eq!(report.outage_periods, vec![(start, 60), (start + Duration::seconds(1000), 120)]);
+        assert_eq!((report.outages, report.longest_outage_seconds), (2, 120));
+    }
+
+    #[test]
+    fn uptime_skips_gaps_longer_than_max_gap() {
+        let (conn, _) = scanned_database(&[(0, "up"), (60, "up"), (1000, "down"), (1060, "up")]);
+        let report = uptime(&mut Database::Sqlite(conn), &Filter::default(), 120).unwrap().remove(0);
+        assert_eq!((report.monitored_seconds, report.downtime_seconds, report.outages), (120, 60, 1));
+        assert_eq!(report.availability, 50.0);
+    }
+
+    #[test]
+    fn map_retention_averages_time_per_player() {
+        let (conn, start) = switched_database();
+        let at = |minutes: i64| to_millis(start + Duration::minutes(minutes));
+        conn.execute("DELETE FROM sessions", []).unwrap();
+        for (map, minutes) in [("pl_upward", 0), ("cp_badlands", 60)] {
+            conn.execute(
+                "INSERT INTO server_settings (server_id, name, max_players, current_map, vac_status, has_password, game_version, bots, created_at) VALUES (1, 'test', 24, ?1, 1, 0, '1', 0, ?2)",
+                params![map, at(minutes)],
+            ).unwrap();
+        }
+        conn.execute("INSERT INTO server_events (server_id, event_type, created_at) VALUES (1, 'up', ?1)", params![at(120)]).unwrap();
+        //a is there when pl_upward starts and leaves after 30 minutes, b joins at 10 and stays 30 minutes into cp_badlands
+        for (player_id, joined, left) in [(1, -10, 30), (2, 10, 90)] {
+            conn.execute(
+                "INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES (1, ?1, 0, 0, ?2, ?3)",
+                params![player_id, at(joined), at(left)],
+            ).unwrap();
+        }
+
+        let maps = map_retention(&mut Database::Sqlite(conn), &Filter::default()).unwrap();
+        let upward = maps.iter().find(|map| map.map == "pl_upward").unwrap();
+        assert_eq!((upward.rounds, upward.avg_players_start, upward.avg_players_end, upward.joined, upward.left), (1, 1.0, 1.0, 1, 1));
+        assert_eq!((upward.avg_minutes_per_player, upward.churn_rate), (40.0, 0.5));
+        let badlands = maps.iter().find(|map| map.map == "cp_badlands").unwrap();
+        assert_eq!((badlands.avg_players_start, badlands.joined, badlands.left), (1.0, 0, 1));
+        assert_eq!((badlands.avg_minutes_per_player, badlands.churn_rate), (30.0, 1.0));
+    }
+
+    #[test]
+    fn answers_the_fixture() {
+        let mut database = fixture_database();
+        assert!(check_schema(&mut database).is_ok());
+        let answers = answers(&mut database);
+        assert!(answers[0].contains("sessions: 2, unique_players: 2"), "{}", answers[0]);
+        assert!(answers[10].contains("previous_version: \"100\", version: \"101\""), "{}", answers[10]);
+    }
+
+    #[test]
+    fn rejects_bad_times() {
+        for input in ["", "yesterday", "2023-13-01", "2023-11-01 25:00:00", "-2h", "9999999999w"] {
+            assert!(parse_time(input).is_err(), "{} should not parse", input);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{html::escape, table::{Format, Table}};
+use std::fmt::Write;
+
+//Everything inline so the page can be posted or mailed as a single file
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 960px; margin: 2em auto; padding: 0 1em; color: #222; }
+h1 { margin-bottom: 0; }
+h2 { border-bottom: 1px solid #ccc; padding-bottom: 0.2em; margin-top: 2em; }
+.period { color: #666; }
+svg { max-width: 100%; height: auto; }
+table { border-collapse: collapse; margin: 1em 0; font-size: 0.9em; }
+th, td { padding: 0.3em 0.8em; border-bottom: 1px solid #eee; text-align: left; }
+th { background: #f4f4f4; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+.empty { color: #666; font-style: italic; }
+";
+
+pub struct Report {
+    pub title: String,
+    pub period: String,
+    pub generated: String,
+    pub sections: Vec<Section>,
+}
+
+pub struct Section {
+    pub title: String,
+    pub description: String,
+    pub charts: Vec<String>,
+    pub tables: Vec<Table>,
+}
+
+pub fn render(report: &Report) -> String {
+    let mut html = String::new();
+    let _ = writeln!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(html, "<title>{}</title>\n<style>{}</style>\n</head>\n<body>", escape(&report.title), STYLE);
+    let _ = writeln!(html, "<h1>{}</h1>", escape(&report.title));
+    let _ = writeln!(html, "<p class=\"period\">{}, generated {}</p>", escape(&report.period), escape(&report.generated));
+
+    for section in &report.sections {
+        let _ = writeln!(html, "<h2>{}</h2>", escape(&section.title));
+        if !section.description.is_empty() {
+            let _ = writeln!(html, "<p>{}</p>", escape(&section.description));
+        }
+        for chart in &section.charts {
+            html.push_str(chart);
+        }
+        for table in &section.tables {
+            match table.is_empty() {
+                true => html.push_str("<p class=\"empty\">No data in this period</p>\n"),
+                false => {
+                    let mut buffer = Vec::new();
+                    let _ = table.write(Format::Html, &mut buffer);
+                    html.push_str(&String::from_utf8_lossy(&buffer));
+                }
+            }
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
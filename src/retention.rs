@@ -0,0 +1,121 @@
+use crate::{duration, metrics::Metrics, storage::{self, Backend, Storage}, Config};
+use chrono::{Duration, Utc};
+use log::{error, info};
+use std::{sync::{Arc, Mutex}, thread};
+
+//Rows deleted per statement, small enough that a scan waiting on the lock isn't noticeably delayed
+const BATCH: usize = 5000;
+
+//Table, the time column compared against its window and how long rows are kept
+pub struct Policy {
+    pub windows: Vec<(&'static str, &'static str, i64)>,
+    pub interval: i64,
+}
+
+impl Policy {
+    pub fn from_config(config: &Config) -> Result<Policy, String> {
+        let tables = [
+            ("player_events", "created_at", &config.retention_player_events),
+            ("sessions", "left_at", &config.retention_sessions),
+            ("server_events", "created_at", &config.retention_server_events),
+            ("server_settings", "created_at", &config.retention_server_settings),
+            ("population_samples", "created_at", &config.retention_population_samples),
+        ];
+
+        let mut windows = Vec::new();
+        for (table, column, window) in tables {
+            if let Some(window) = window {
+                match duration::parse_duration(window) {
+                    Ok(seconds) if seconds > 0 => windows.push((table, column, seconds)),
+                    _ => return Err(format!("Invalid retention for {} ({})", table, window)),
+                }
+            }
+        }
+
+        match duration::parse_duration(&config.retention_interval) {
+            Ok(interval) if interval > 0 => Ok(Policy { windows, interval }),
+            _ => Err(format!("Invalid retention_interval ({})", config.retention_interval)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    //Players are only names, once nothing refers to them they are dropped too
+    fn prunes_players(&self) -> bool {
+        self.windows.iter().any(|(table, _, _)| *table == "player_events" || *table == "sessions")
+    }
+}
+
+//Deletes everything outside the windows, taking the lock for each batch so the scanner's writes go in between
+pub fn prune(storage: &mut dyn Storage, policy: &Policy, lock: &Mutex<()>) -> storage::Result<Vec<(&'static str, usize)>> {
+    let now = Utc::now();
+    let mut pruned = Vec::new();
+
+    for (table, column, seconds) in &policy.windows {
+        let cutoff = now - Duration::seconds(*seconds);
+        let mut deleted = 0;
+        loop {
+            let batch = {
+                let _guard = lock.lock().unwrap();
+                storage.delete_batch_before(table, column, cutoff, BATCH)?
+            };
+            deleted += batch;
+            if batch < BATCH {
+                break;
+            }
+        }
+        pruned.push((*table, deleted));
+    }
+
+    if policy.prunes_players() {
+        let mut deleted = 0;
+        loop {
+            let batch = {
+                let _guard = lock.lock().unwrap();
+                storage.delete_batch_unreferenced_players(BATCH)?
+            };
+            deleted += batch;
+            if batch < BATCH {
+                break;
+            }
+        }
+        pruned.push(("players", deleted));
+    }
+
+    Ok(pruned)
+}
+
+//Prunes on startup and then every interval with its own connection
+pub fn spawn(backend: Backend, policy: Policy, lock: Arc<Mutex<()>>, metrics: Arc<Metrics>) {
+    thread::spawn(move || {
+        let mut storage = match backend.open() {
+            Ok(storage) => storage,
+            Err(e) => {
+                error!("Failed to open database for pruning ({})", e);
+                return;
+            }
+        };
+
+        loop {
+            match prune(storage.as_mut(), &policy, &lock) {
+                Ok(pruned) => {
+                    for (table, rows) in pruned.into_iter().filter(|(_, rows)| *rows > 0) {
+                        metrics.rows_pruned.with_label_values(&[table]).inc_by(rows as u64);
+                        info!(table = table, rows = rows; "Pruned ({}) rows from {}", rows, table);
+                    }
+                }
+                //The connection may be what failed, the next run gets a new one
+                Err(e) => {
+                    error!("Failed to prune database ({})", e);
+                    match backend.open() {
+                        Ok(reopened) => storage = reopened,
+                        Err(e) => error!("Failed to reconnect to database for pruning ({})", e),
+                    }
+                }
+            }
+            thread::sleep(std::time::Duration::from_secs(policy.interval as u64));
+        }
+    });
+}
@@ -0,0 +1,116 @@
+use crate::{duration, Config};
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+//Point changes of a session, one sample is written per interval and the last score seen when the player leaves
+pub struct Timeline {
+    //None when point changes aren't stored at all
+    interval: Option<Duration>,
+    sessions: HashMap<(i32, String), Sample>,
+}
+
+struct Sample {
+    written_at: Instant,
+    //Latest score not written yet because a sample was taken too recently
+    pending: Option<i64>,
+}
+
+impl Timeline {
+    pub fn from_config(config: &Config) -> Result<Timeline, String> {
+        Timeline::new(&config.score_sample_interval)
+    }
+
+    //A duration such as "1m" or "0s" for every change, "off" for none
+    fn new(interval: &str) -> Result<Timeline, String> {
+        let interval = match interval {
+            "off" => None,
+            interval => match duration::parse_duration(interval) {
+                Ok(seconds) if seconds >= 0 => Some(Duration::from_secs(seconds as u64)),
+                _ => return Err(format!("Invalid score_sample_interval ({}), expected a duration or off", interval)),
+            },
+        };
+        Ok(Timeline { interval, sessions: HashMap::new() })
+    }
+
+    pub fn describe(&self) -> String {
+        match self.interval {
+            None => "Point changes are not stored".to_string(),
+            Some(interval) if interval.is_zero() => "Storing every point change".to_string(),
+            Some(interval) => format!("Storing at most one point change per player every {}s", interval.as_secs()),
+        }
+    }
+
+    //Score to write for a point change, None if the session was sampled within the interval
+    pub fn sample(&mut self, server_id: i32, name: &str, score: i64) -> Option<i64> {
+        let interval = self.interval?;
+        match self.sessions.get_mut(&(server_id, name.to_string())) {
+            Some(sample) if sample.written_at.elapsed() < interval => {
+                sample.pending = Some(score);
+                None
+            },
+            _ => {
+                self.sessions.insert((server_id, name.to_string()), Sample { written_at: Instant::now(), pending: None });
+                Some(score)
+            },
+        }
+    }
+
+    //Final score to write when a session ends, None if its last change was already written
+    pub fn finish(&mut self, server_id: i32, name: &str) -> Option<i64> {
+        self.sessions.remove(&(server_id, name.to_string()))?.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn samples_once_per_interval_and_keeps_the_latest_score() {
+        let mut timeline = Timeline::new("1h").unwrap();
+        assert_eq!(timeline.sample(1, "a", 5), Some(5));
+        assert_eq!(timeline.sample(1, "a", 7), None);
+        assert_eq!(timeline.sample(1, "a", 9), None);
+        //Sessions are kept apart by server and name
+        assert_eq!(timeline.sample(2, "a", 3), Some(3));
+        assert_eq!(timeline.sample(1, "b", 1), Some(1));
+        assert_eq!(timeline.finish(1, "a"), Some(9));
+        assert_eq!(timeline.finish(1, "a"), None);
+        assert_eq!(timeline.finish(2, "a"), None);
+    }
+
+    #[test]
+    fn samples_again_once_the_interval_has_passed() {
+        let mut timeline = Timeline { interval: Some(Duration::from_millis(20)), sessions: HashMap::new() };
+        assert_eq!(timeline.sample(1, "a", 5), Some(5));
+        assert_eq!(timeline.sample(1, "a", 6), None);
+        sleep(Duration::from_millis(30));
+        assert_eq!(timeline.sample(1, "a", 7), Some(7));
+        //The pending 6 was superseded by the written 7
+        assert_eq!(timeline.finish(1, "a"), None);
+    }
+
+    #[test]
+    fn zero_stores_every_change() {
+        let mut timeline = Timeline::new("0s").unwrap();
+        for score in 1..5 {
+            assert_eq!(timeline.sample(1, "a", score), Some(score));
+        }
+        assert_eq!(timeline.finish(1, "a"), None);
+    }
+
+    #[test]
+    fn off_stores_nothing() {
+        let mut timeline = Timeline::new("off").unwrap();
+        assert_eq!(timeline.sample(1, "a", 5), None);
+        assert_eq!(timeline.finish(1, "a"), None);
+        assert_eq!(timeline.describe(), "Point changes are not stored");
+    }
+
+    #[test]
+    fn rejects_bad_intervals() {
+        for interval in ["", "never", "-1m", "1y"] {
+            assert!(Timeline::new(interval).is_err(), "{} should not parse", interval);
+        }
+    }
+}
@@ -1,8 +1,10 @@
+
 extern crate rusqlite;
 extern crate chrono;
 
-use rusqlite::{params, Connection, Result, Row};
-use chrono::NaiveDateTime;
+use rusqlite::{params, types::Type, Connection, OpenFlags, Result, Row};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 
 
 #[derive(Debug)]
@@ -22,7 +24,7 @@ pub struct ServerSettings {
     pub has_password: bool,
     pub game_version: String,
     pub bots: u8,
-    pub created_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug)]
@@ -38,17 +40,16 @@ pub struct Session {
     pub player_id: i32,
     pub score: i32,
     pub duration: f64,
-    pub joined_at: NaiveDateTime,
-    pub left_at: NaiveDateTime,
+    pub joined_at: DateTime<Utc>,
+    pub left_at: DateTime<Utc>,
 }
 
 #[derive(Debug)]
 pub struct ServerEvent {
     pub event_id: i32,
     pub server_id: i32,
-    pub event_type: String,
-    pub event_data: String,
-    pub created_at: NaiveDateTime,
+    pub kind: ServerEventKind,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug)]
@@ -56,9 +57,241 @@ pub struct PlayerEvent {
     pub event_id: i32,
     pub server_id: i32,
     pub player_id: i32,
-    pub event_type: String,
-    pub event_data: String, 
-    pub created_at: NaiveDateTime,
+    pub kind: PlayerEventKind,
+    pub created_at: DateTime<Utc>,
+}
+
+//Stored as the event_type string with any payload as a json object in event_data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEventKind {
+    Up,
+    Down,
+    SettingChange { map: String },
+}
+
+impl ServerEventKind {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            ServerEventKind::Up => "up",
+            ServerEventKind::Down => "down",
+            ServerEventKind::SettingChange { .. } => "setting change",
+        }
+    }
+
+    pub fn event_data(&self) -> Option<String> {
+        match self {
+            ServerEventKind::SettingChange { map } => Some(object! { map: map.as_str() }.dump()),
+            _ => None,
+        }
+    }
+
+    pub fn from_columns(event_type: &str, event_data: Option<&str>) -> std::result::Result<ServerEventKind, String> {
+        match event_type {
+            "up" => Ok(ServerEventKind::Up),
+            "down" => Ok(ServerEventKind::Down),
+            "setting change" => match payload(event_data)?["map"].as_str() {
+                Some(map) => Ok(ServerEventKind::SettingChange { map: map.to_string() }),
+                None => Err(format!("setting change without a map ({:?})", event_data)),
+            },
+            _ => Err(format!("unknown server event type ({})", event_type)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerEventKind {
+    Join,
+    Leave,
+    TargetJoin,
+    TargetLeave,
+    PointChange { score: i64 },
+}
+
+impl PlayerEventKind {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            PlayerEventKind::Join => "join",
+            PlayerEventKind::Leave => "leave",
+            PlayerEventKind::TargetJoin => "target join",
+            PlayerEventKind::TargetLeave => "target leave",
+            PlayerEventKind::PointChange { .. } => "point change",
+        }
+    }
+
+    pub fn event_data(&self) -> Option<String> {
+        match self {
+            PlayerEventKind::PointChange { score } => Some(object! { score: *score }.dump()),
+            _ => None,
+        }
+    }
+
+    pub fn from_columns(event_type: &str, event_data: Option<&str>) -> std::result::Result<PlayerEventKind, String> {
+        match event_type {
+            "join" => Ok(PlayerEventKind::Join),
+            "leave" => Ok(PlayerEventKind::Leave),
+            "target join" => Ok(PlayerEventKind::TargetJoin),
+            "target leave" => Ok(PlayerEventKind::TargetLeave),
+            "point change" => match payload(event_data)?["score"].as_i64() {
+                Some(score) => Ok(PlayerEventKind::PointChange { score }),
+                None => Err(format!("point change without a score ({:?})", event_data)),
+            },
+            _ => Err(format!("unknown player event type ({})", event_type)),
+        }
+    }
+}
+
+fn payload(event_data: Option<&str>) -> std::result::Result<json::JsonValue, String> {
+    json::parse(event_data.unwrap_or("null")).map_err(|e| format!("invalid event_data ({})", e))
+}
+
+//Reads the event_type and event_data columns at index and index + 1
+fn server_event_kind(row: &Row, index: usize) -> Result<ServerEventKind> {
+    ServerEventKind::from_columns(&row.get::<_, String>(index)?, row.get::<_, Option<String>>(index + 1)?.as_deref())
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, e.into()))
+}
+
+fn player_event_kind(row: &Row, index: usize) -> Result<PlayerEventKind> {
+    PlayerEventKind::from_columns(&row.get::<_, String>(index)?, row.get::<_, Option<String>>(index + 1)?.as_deref())
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, e.into()))
+}
+
+//Schema changes made after up.sql, applied in order on startup and counted in PRAGMA user_version.
+//Each is written so it can also run on a database created from an up.sql that already includes it.
+const MIGRATIONS: [&str; 5] = [
+    "CREATE TABLE IF NOT EXISTS population_samples (
+        sample_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        server_id INTEGER NOT NULL REFERENCES servers(server_id),
+        players INTEGER NOT NULL,
+        bots INTEGER NOT NULL,
+        players_joined INTEGER NOT NULL,
+        players_left INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    "CREATE INDEX IF NOT EXISTS server_settings_server_created ON server_settings(server_id, created_at);
+    CREATE INDEX IF NOT EXISTS sessions_server_joined ON sessions(server_id, joined_at);
+    CREATE INDEX IF NOT EXISTS sessions_player ON sessions(player_id);
+    CREATE INDEX IF NOT EXISTS server_events_server_created ON server_events(server_id, created_at);
+    CREATE INDEX IF NOT EXISTS player_events_player_created ON player_events(player_id, created_at);
+    CREATE INDEX IF NOT EXISTS player_events_server_created ON player_events(server_id, created_at);
+    CREATE INDEX IF NOT EXISTS population_samples_server_created ON population_samples(server_id, created_at);",
+    //Local time strings to utc unix milliseconds, done by convert_local_times with the configured zone
+    "",
+    //Bare map names and scores to json payloads, events without one hold NULL instead of an empty string
+    "UPDATE server_events SET event_data = json_object('map', event_data) WHERE event_type = 'setting change' AND event_data NOT LIKE '{%';
+    UPDATE server_events SET event_data = NULL WHERE event_type != 'setting change' AND event_data IS NOT NULL;
+    UPDATE player_events SET event_data = json_object('score', CAST(event_data AS INTEGER)) WHERE event_type = 'point change' AND event_data NOT LIKE '{%';
+    UPDATE player_events SET event_data = NULL WHERE event_type != 'point change' AND event_data IS NOT NULL;",
+    //Players stored as pseudonyms, names written before this table existed are marked by the scanner or db pseudonymise
+    "CREATE TABLE IF NOT EXISTS pseudonyms (
+        player_id INTEGER PRIMARY KEY REFERENCES players(player_id) ON DELETE CASCADE
+    );",
+];
+
+//Index of the migration converting local time strings, which needs the zone they were written in
+pub const LOCAL_TIMES: usize = 2;
+
+//Every column that held a local time string, with the key of its table
+const LOCAL_TIME_COLUMNS: [(&str, &str, &str); 6] = [
+    ("server_settings", "setting_id", "created_at"),
+    ("sessions", "session_id", "joined_at"),
+    ("sessions", "session_id", "left_at"),
+    ("server_events", "event_id", "created_at"),
+    ("player_events", "event_id", "created_at"),
+    ("population_samples", "sample_id", "created_at"),
+];
+
+//Opens a connection for writing, sqlite only enforces the REFERENCES in the schema when asked to on every connection
+pub fn open(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(conn)
+}
+
+//Like open but fails instead of creating an empty database, for maintenance on a file that should already exist
+pub fn open_existing(path: &str) -> Result<Connection> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(conn)
+}
+
+//For commands that only read, the file is left exactly as it was, schema version included
+pub fn open_read_only(path: &str) -> Result<Connection> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+}
+
+//Brings the schema up to date, returns the number of migrations applied.
+//Local time strings are only converted with legacy_time_zone set, without it a database holding any fails to migrate.
+pub fn migrate(conn: &mut Connection, legacy_time_zone: Option<Tz>) -> std::result::Result<usize, String> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(|e| e.to_string())?;
+    if version >= MIGRATIONS.len() {
+        return Ok(0);
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        match index {
+            LOCAL_TIMES => convert_local_times(&tx, legacy_time_zone)?,
+            _ => tx.execute_batch(migration).map_err(|e| e.to_string())?,
+        }
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len()).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(MIGRATIONS.len() - version)
+}
+
+fn convert_local_times(conn: &Connection, zone: Option<Tz>) -> std::result::Result<(), String> {
+    for (table, key, column) in LOCAL_TIME_COLUMNS {
+        let rows: Vec<(i64, String)> = conn
+            .prepare(&format!("SELECT {}, {} FROM {} WHERE typeof({}) = 'text'", key, column, table, column))
+            .and_then(|mut stmt| stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect())
+            .map_err(|e| e.to_string())?;
+        if rows.is_empty() {
+            continue;
+        }
+        let zone = match zone {
+            Some(zone) => zone,
+            None => return Err("Database holds local time strings, set legacy_time_zone to the zone they were written in (eg. Europe/Berlin) to convert them".to_string()),
+        };
+
+        let mut update = conn.prepare(&format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, key)).map_err(|e| e.to_string())?;
+        for (id, time) in rows {
+            update.execute(params![local_to_millis(&time, zone)?, id]).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+//A local time string as utc unix milliseconds. The hour repeated when daylight saving ends is read as the first one,
+//a time skipped when it starts with the offset from before it.
+pub fn local_to_millis(time: &str, zone: Tz) -> std::result::Result<i64, String> {
+    let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").map_err(|e| format!("Invalid local time ({}) ({})", time, e))?;
+    Ok(naive_to_millis(time, zone))
+}
+
+fn naive_to_millis(time: NaiveDateTime, zone: Tz) -> i64 {
+    match zone.from_local_datetime(&time).earliest() {
+        Some(time) => time.timestamp_millis(),
+        None => naive_to_millis(time - Duration::hours(1), zone) + 3600000,
+    }
+}
+
+pub fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)", params![table], |row| row.get(0))
+}
+
+//Whether any time column still holds a local time string, tables a database doesn't have yet are skipped
+pub fn has_local_times(conn: &Connection) -> Result<bool> {
+    for (table, _, column) in LOCAL_TIME_COLUMNS {
+        let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)", params![table], |row| row.get(0))?;
+        if exists && conn.query_row(&format!("SELECT EXISTS (SELECT 1 FROM {} WHERE typeof({}) = 'text')", table, column), [], |row| row.get(0))? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub fn schema_version(conn: &Connection) -> Result<(usize, usize)> {
+    Ok((conn.query_row("PRAGMA user_version", [], |row| row.get(0))?, MIGRATIONS.len()))
 }
 
 pub fn insert_server(conn: &Connection, server: &Server) -> Result<usize> {
@@ -72,7 +305,7 @@ pub fn get_server(conn: &Connection, server_id: i32) -> Result<Server> {
     conn.query_row(
         "SELECT * FROM servers WHERE server_id = ?1",
         params![server_id],
-        |row| Ok(map_to_server(row))
+        map_to_server
     )
 }
 
@@ -80,7 +313,7 @@ pub fn get_server_by_addr(conn: &Connection, address: String) -> Result<Server>
     conn.query_row(
         "SELECT * FROM servers WHERE address = ?1",
         params![address],
-        |row| Ok(map_to_server(row))
+        map_to_server
     )
 }
 
@@ -96,7 +329,7 @@ pub fn insert_server_settings(conn: &Connection, settings: &ServerSettings) -> R
             settings.has_password,
             &settings.game_version,
             settings.bots,
-            settings.created_at.format("%Y-%m-%d %H:%M:%S").to_string()
+            settings.created_at.timestamp_millis()
         ],
     )
 }
@@ -116,27 +349,40 @@ pub fn get_server_settings(conn: &Connection, server_id: i32) -> Result<ServerSe
                 has_password: row.get(6)?,
                 game_version: row.get(7)?,
                 bots: row.get(8)?,
-                created_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(9).unwrap(), "%Y-%m-%d %H:%M:%S").unwrap(),
+                created_at: from_millis(row.get(9)?),
             })
         },
     )
 }
 
-fn map_to_server(row: &Row) -> Server {
-    Server {
-        server_id: row.get(0).unwrap(),
-        address: row.get(1).unwrap(),
-    }
+//Times are stored as utc unix milliseconds
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap()
 }
 
-pub fn insert_players_batch(conn: &mut Connection, players: &[Player]) -> Result<usize> {
-    let tx = conn.transaction()?;
+fn map_to_server(row: &Row) -> Result<Server> {
+    Ok(Server {
+        server_id: row.get(0)?,
+        address: row.get(1)?,
+    })
+}
+
+//Inserts the names not stored yet, pseudonyms marks them as written by a pseudonymiser.
+//A savepoint rather than a transaction, so it also works inside the transaction of a scan.
+pub fn insert_players_batch(conn: &mut Connection, players: &[Player], pseudonyms: bool) -> Result<usize> {
+    let tx = conn.savepoint()?;
     {
         let mut stmt = tx.prepare("INSERT OR IGNORE INTO players (name) VALUES (?1) ON CONFLICT (name) DO NOTHING")?;
         for player in players {
             stmt.execute(params![&player.name])?;
         }
     }
+    if pseudonyms {
+        let mut stmt = tx.prepare("INSERT OR IGNORE INTO pseudonyms (player_id) SELECT player_id FROM players WHERE name = ?1")?;
+        for player in players {
+            stmt.execute(params![&player.name])?;
+        }
+    }
     tx.commit()?;
     Ok(players.len())
 }
@@ -176,7 +422,7 @@ pub fn get_player_by_name(conn: &Connection, name: String) -> Result<Player> {
 pub fn insert_session(conn: &Connection, name: &String,session: &Session) -> Result<usize> {
     conn.execute(
         "INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES (?1, (SELECT player_id FROM players WHERE name = ?2), ?3, ?4, ?5, ?6)",
-        params![session.server_id, name, session.score, session.duration, session.joined_at.format("%Y-%m-%d %H:%M:%S").to_string(), session.left_at.format("%Y-%m-%d %H:%M:%S").to_string()]
+        params![session.server_id, name, session.score, session.duration, session.joined_at.timestamp_millis(), session.left_at.timestamp_millis()]
     )
 }
 
@@ -186,13 +432,13 @@ pub fn get_session(conn: &Connection, session_id: i32) -> Result<Session> {
         params![session_id],
         |row| {
             Ok(Session {
-                session_id: row.get(0).unwrap(),
-                server_id: row.get(1).unwrap(),
-                player_id: row.get(2).unwrap(),
-                score: row.get(3).unwrap(),
-                duration: row.get(4).unwrap(),
-                joined_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(5).unwrap(), "%Y-%m-%d %H:%M:%S").unwrap(),
-                left_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(6).unwrap(), "%Y-%m-%d %H:%M:%S").unwrap(),
+                session_id: row.get(0)?,
+                server_id: row.get(1)?,
+                player_id: row.get(2)?,
+                score: row.get(3)?,
+                duration: row.get(4)?,
+                joined_at: from_millis(row.get(5)?),
+                left_at: from_millis(row.get(6)?),
             })
         },
     )
@@ -211,14 +457,8 @@ pub fn get_all_sessions(conn: &Connection) -> Result<Vec<Session>> {
             player_id: row.get(2)?,
             score: row.get(3)?,
             duration: row.get(4)?,
-            joined_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(5)?,
-                "%Y-%m-%d %H:%M:%S"
-            ).unwrap(),
-            left_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(6)?,
-                "%Y-%m-%d %H:%M:%S"
-            ).unwrap(),
+            joined_at: from_millis(row.get(5)?),
+            left_at: from_millis(row.get(6)?),
         };
         sessions.push(session);
     }
@@ -229,7 +469,7 @@ pub fn get_all_sessions(conn: &Connection) -> Result<Vec<Session>> {
 pub fn insert_server_event(conn: &Connection, event: &ServerEvent) -> Result<()> {
     conn.execute(
         "INSERT INTO server_events (server_id, event_type, event_data, created_at) VALUES (?1, ?2, ?3, ?4)",
-        params![event.server_id, &event.event_type, &event.event_data, event.created_at.format("%Y-%m-%d %H:%M:%S").to_string()],
+        params![event.server_id, event.kind.event_type(), event.kind.event_data(), event.created_at.timestamp_millis()],
     )?;
     Ok(())
 }
@@ -242,9 +482,8 @@ pub fn get_server_event(conn: &Connection, event_id: i32) -> Result<ServerEvent>
             Ok(ServerEvent {
                 event_id: row.get(0)?,
                 server_id: row.get(1)?,
-                event_type: row.get(2)?,
-                event_data: row.get(3)?,
-                created_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(4).unwrap(), "%Y-%m-%d %H:%M:%S").unwrap(),
+                kind: server_event_kind(row, 2)?,
+                created_at: from_millis(row.get(4)?),
             })
         },
     )
@@ -260,17 +499,13 @@ pub fn get_all_server_events(conn: &Connection) -> Result<Vec<ServerEvent>>{
         let server_event = ServerEvent{
             event_id: row.get(0)?,
             server_id: row.get(1)?,
-            event_type: row.get(2)?,
-            event_data: row.get(3)?,
-            created_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(4)?, 
-                "%Y-%m-%d %H:%M:%S"
-            ).unwrap(),
+            kind: server_event_kind(row, 2)?,
+            created_at: from_millis(row.get(4)?),
         };
         server_events.push(server_event);
     }
 
-    return Ok(server_events);
+    Ok(server_events)
 }
 
 // pub fn insert_player_event(conn: &Connection, event: &PlayerEvent) -> Result<()> {
@@ -288,9 +523,9 @@ pub fn insert_player_event(conn: &Connection, player_name: &String, event: &Play
         params![
             event.server_id,
             &player_name,
-            &event.event_type,
-            &event.event_data,
-            event.created_at.format("%Y-%m-%d %H:%M:%S").to_string()
+            event.kind.event_type(),
+            event.kind.event_data(),
+            event.created_at.timestamp_millis()
         ],
     )?;
     Ok(())
@@ -305,9 +540,8 @@ pub fn _get_player_event(conn: &Connection, event_id: i32) -> Result<PlayerEvent
                 event_id: row.get(0)?,
                 server_id: row.get(1)?,
                 player_id: row.get(2)?,
-                event_type: row.get(3)?,
-                event_data: row.get(4)?,
-                created_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(5).unwrap(), "%Y-%m-%d %H:%M:%S").unwrap(),
+                kind: player_event_kind(row, 3)?,
+                created_at: from_millis(row.get(5)?),
             })
         },
     )
@@ -324,11 +558,218 @@ pub fn get_all_player_events(conn: &Connection) -> Result<Vec<PlayerEvent>>{
             event_id: row.get(0)?,
             server_id: row.get(1)?,
             player_id: row.get(2)?,
-            event_type: row.get(3)?,
-            event_data: row.get(4)?,
-            created_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(5).unwrap(), "%Y-%m-%d %H:%M:%S").unwrap(),
+            kind: player_event_kind(row, 3)?,
+            created_at: from_millis(row.get(5)?),
         };
         player_events.push(player_event);
     }
-    return Ok(player_events);
-}
\ No newline at end of file
+    Ok(player_events)
+}
+#[derive(Debug)]
+pub struct PopulationSample {
+    pub sample_id: i32,
+    pub server_id: i32,
+    pub players: i32,
+    pub bots: i32,
+    pub players_joined: i32,
+    pub players_left: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+pub fn insert_population_sample(conn: &Connection, sample: &PopulationSample) -> Result<()> {
+    conn.execute(
+        "INSERT INTO population_samples (server_id, players, bots, players_joined, players_left, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![sample.server_id, sample.players, sample.bots, sample.players_joined, sample.players_left, sample.created_at.timestamp_millis()],
+    )?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct DatabaseStats {
+    pub tables: Vec<(String, i64)>,
+    pub page_count: i64,
+    pub page_size: i64,
+    pub freelist_count: i64,
+}
+
+pub fn get_database_stats(conn: &Connection) -> Result<DatabaseStats> {
+    let mut tables = Vec::new();
+    for table in ["servers", "server_settings", "server_events", "players", "sessions", "player_events"] {
+        let rows: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))?;
+        tables.push((table.to_string(), rows));
+    }
+    //Tables added by migrations are missing until the scanner has run against the file
+    for table in ["population_samples", "pseudonyms"] {
+        if table_exists(conn, table)? {
+            let rows: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))?;
+            tables.push((table.to_string(), rows));
+        }
+    }
+
+    Ok(DatabaseStats {
+        tables,
+        page_count: conn.query_row("PRAGMA page_count", [], |row| row.get(0))?,
+        page_size: conn.query_row("PRAGMA page_size", [], |row| row.get(0))?,
+        freelist_count: conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?,
+    })
+}
+
+pub fn vacuum(conn: &Connection) -> Result<()> {
+    conn.execute_batch("VACUUM")
+}
+
+pub fn analyze(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ANALYZE")
+}
+
+//Copies the database page by page with the online backup api, pausing between steps so a running scanner can still write
+pub fn backup(conn: &Connection, path: &str) -> Result<()> {
+    let mut destination = Connection::open(path)?;
+    let backup = rusqlite::backup::Backup::new(conn, &mut destination)?;
+    backup.run_to_completion(1000, std::time::Duration::from_millis(10), None)
+}
+
+//Problems found by sqlite's own integrity check, empty when the file is fine
+pub fn integrity_check(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let messages = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>>>()?;
+    Ok(messages.into_iter().filter(|message| message != "ok").collect())
+}
+
+//Rows pointing at a server or player that doesn't exist, counted by table and the missing parent
+pub fn foreign_key_violations(conn: &Connection) -> Result<Vec<(String, String, i64)>> {
+    let mut stmt = conn.prepare("SELECT \"table\", parent, COUNT(*) FROM pragma_foreign_key_check GROUP BY 1, 2 ORDER BY 1, 2")?;
+    let violations = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    violations.collect()
+}
+
+//Deletes up to limit rows older than the cutoff, called repeatedly so each statement holds the write lock briefly
+pub fn delete_batch_before(conn: &Connection, table: &str, column: &str, cutoff: DateTime<Utc>, limit: usize) -> Result<usize> {
+    //The latest settings of each server are what the scanner compares new info against, so they are always kept
+    let keep = match table {
+        "server_settings" => " AND setting_id NOT IN (SELECT MAX(setting_id) FROM server_settings GROUP BY server_id)",
+        _ => "",
+    };
+    conn.execute(
+        &format!("DELETE FROM {0} WHERE rowid IN (SELECT rowid FROM {0} WHERE {1} < ?1{2} LIMIT ?2)", table, column, keep),
+        params![cutoff.timestamp_millis(), limit as i64],
+    )
+}
+
+//Deletes up to limit players no session or player event refers to anymore
+pub fn delete_batch_unreferenced_players(conn: &Connection, limit: usize) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM players WHERE player_id IN (
+            SELECT player_id FROM players p
+            WHERE NOT EXISTS (SELECT 1 FROM sessions s WHERE s.player_id = p.player_id)
+            AND NOT EXISTS (SELECT 1 FROM player_events e WHERE e.player_id = p.player_id)
+            LIMIT ?1)",
+        params![limit as i64],
+    )
+}
+
+//Players whose names aren't pseudonyms
+pub fn get_plain_players(conn: &Connection) -> Result<Vec<Player>> {
+    let mut stmt = conn.prepare("SELECT player_id, name FROM players WHERE player_id NOT IN (SELECT player_id FROM pseudonyms)")?;
+    let players = stmt.query_map([], |row| Ok(Player { player_id: row.get(0)?, name: row.get(1)? }))?;
+    players.collect()
+}
+
+//Replaces names with pseudonyms in one transaction, a player whose new name is already taken is merged into that row
+pub fn rename_players(conn: &mut Connection, renames: &[(i32, String)]) -> Result<usize> {
+    let tx = conn.transaction()?;
+    for (player_id, name) in renames {
+        let existing: Option<i32> = match tx.query_row("SELECT player_id FROM players WHERE name = ?1", params![name], |row| row.get(0)) {
+            Ok(existing) => Some(existing),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e),
+        };
+        match existing {
+            Some(existing) if existing != *player_id => {
+                tx.execute("UPDATE sessions SET player_id = ?1 WHERE player_id = ?2", params![existing, player_id])?;
+                tx.execute("UPDATE player_events SET player_id = ?1 WHERE player_id = ?2", params![existing, player_id])?;
+                tx.execute("DELETE FROM players WHERE player_id = ?1", params![player_id])?;
+            }
+            Some(_) => (),
+            None => {
+                tx.execute("UPDATE players SET name = ?1 WHERE player_id = ?2", params![name, player_id])?;
+            }
+        }
+        tx.execute("INSERT OR IGNORE INTO pseudonyms (player_id) SELECT player_id FROM players WHERE name = ?1", params![name])?;
+    }
+    tx.commit()?;
+    Ok(renames.len())
+}
+
+//Deletes a player with all of their sessions and events, None if the name isn't stored
+pub fn delete_player(conn: &mut Connection, name: &str) -> Result<Option<(usize, usize)>> {
+    let tx = conn.transaction()?;
+    let player_id: i32 = match tx.query_row("SELECT player_id FROM players WHERE name = ?1", params![name], |row| row.get(0)) {
+        Ok(player_id) => player_id,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let sessions = tx.execute("DELETE FROM sessions WHERE player_id = ?1", params![player_id])?;
+    let events = tx.execute("DELETE FROM player_events WHERE player_id = ?1", params![player_id])?;
+    tx.execute("DELETE FROM players WHERE player_id = ?1", params![player_id])?;
+    tx.commit()?;
+    Ok(Some((sessions, events)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Europe/Berlin switches from +01:00 to +02:00 at 02:00 on 2023-03-26 and back at 03:00 on 2023-10-29
+    fn utc(time: &str) -> i64 {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp_millis()
+    }
+
+    #[test]
+    fn converts_local_times_across_daylight_saving() {
+        let berlin = chrono_tz::Europe::Berlin;
+        assert_eq!(local_to_millis("2023-03-26 01:59:59", berlin), Ok(utc("2023-03-26 00:59:59")));
+        assert_eq!(local_to_millis("2023-03-26 03:00:00", berlin), Ok(utc("2023-03-26 01:00:00")));
+        assert_eq!(local_to_millis("2023-03-26 02:30:00", berlin), Ok(utc("2023-03-26 01:30:00")));
+        assert_eq!(local_to_millis("2023-10-29 02:30:00", berlin), Ok(utc("2023-10-29 00:30:00")));
+        assert_eq!(local_to_millis("2023-10-29 03:30:00", berlin), Ok(utc("2023-10-29 02:30:00")));
+        assert!(local_to_millis("2023-10-29", berlin).is_err());
+    }
+
+    //A database at the schema version before times were converted, holding one session across the October change
+    fn legacy_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        conn.pragma_update(None, "user_version", LOCAL_TIMES).unwrap();
+        conn.execute_batch(
+            "INSERT INTO servers (address) VALUES ('127.0.0.1:27015');
+            INSERT INTO players (name) VALUES ('a');
+            INSERT INTO sessions (server_id, player_id, score, duration, joined_at, left_at) VALUES (1, 1, 0, 7200, '2023-10-29 01:30:00', '2023-10-29 03:30:00');",
+        ).unwrap();
+        conn
+    }
+
+    #[test]
+    fn migrates_local_times_with_the_configured_zone() {
+        let mut conn = legacy_database();
+        assert_eq!(migrate(&mut conn, Some(chrono_tz::Europe::Berlin)), Ok(MIGRATIONS.len() - LOCAL_TIMES));
+        let (joined_at, left_at): (i64, i64) = conn.query_row("SELECT joined_at, left_at FROM sessions", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!((joined_at, left_at), (utc("2023-10-28 23:30:00"), utc("2023-10-29 02:30:00")));
+    }
+
+    #[test]
+    fn refuses_local_times_without_a_zone() {
+        let mut conn = legacy_database();
+        assert!(migrate(&mut conn, None).unwrap_err().contains("legacy_time_zone"));
+        assert_eq!(schema_version(&conn).unwrap().0, LOCAL_TIMES);
+        let joined_at: String = conn.query_row("SELECT joined_at FROM sessions", [], |row| row.get(0)).unwrap();
+        assert_eq!(joined_at, "2023-10-29 01:30:00");
+    }
+
+    #[test]
+    fn migrates_databases_without_local_times_without_a_zone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        assert_eq!(migrate(&mut conn, None), Ok(MIGRATIONS.len()));
+    }
+}
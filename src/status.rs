@@ -0,0 +1,176 @@
+use crate::{events::{self, Broadcaster}, format_duration, html::escape, Player};
+use a2s::info::Info;
+use chrono::{DateTime, Local};
+use std::{collections::HashMap, fmt::Write, net::SocketAddr, sync::{Arc, RwLock}, thread};
+use tiny_http::{Header, Response, Server};
+
+//Result of the latest query of a server, alongside the info and players the scanner already keeps
+#[derive(Debug, Clone)]
+pub struct ServerState {
+    pub up: bool,
+    pub latency_ms: Option<u128>,
+    pub last_scan: DateTime<Local>,
+    pub last_seen: Option<DateTime<Local>>,
+    pub last_change: Option<DateTime<Local>>,
+}
+
+//Totals of the last finished scan
+#[derive(Debug, Clone)]
+pub struct ScanHealth {
+    pub finished: DateTime<Local>,
+    pub scans: u64,
+    pub scan_ms: u128,
+    pub db_ms: u128,
+    pub servers_ok: usize,
+    pub servers_failed: usize,
+    pub players: usize,
+    pub events: u64,
+}
+
+//The scanner's running memory, shared with the status page, event stream and dashboard
+#[derive(Clone)]
+pub struct LiveState {
+    pub servers: Vec<SocketAddr>,
+    pub info: Arc<RwLock<HashMap<SocketAddr, Info>>>,
+    pub players: Arc<RwLock<HashMap<SocketAddr, Vec<Player>>>>,
+    pub states: Arc<RwLock<HashMap<SocketAddr, ServerState>>>,
+    pub health: Arc<RwLock<Option<ScanHealth>>>,
+    pub broadcaster: Arc<Broadcaster>,
+    pub opt_out: Arc<RwLock<Vec<String>>>,
+    //Why the scanner gave up, the dashboard closes once it is set
+    pub stopped: Arc<RwLock<Option<String>>>,
+}
+
+impl LiveState {
+    pub fn new(servers: Vec<SocketAddr>) -> LiveState {
+        LiveState {
+            servers,
+            info: Arc::new(RwLock::new(HashMap::new())),
+            players: Arc::new(RwLock::new(HashMap::new())),
+            states: Arc::new(RwLock::new(HashMap::new())),
+            health: Arc::new(RwLock::new(None)),
+            broadcaster: Arc::new(Broadcaster::default()),
+            opt_out: Arc::new(RwLock::new(Vec::new())),
+            stopped: Arc::new(RwLock::new(None)),
+        }
+    }
+}
+
+pub struct StatusPage {
+    pub live: LiveState,
+    pub refresh_seconds: u64,
+}
+
+impl StatusPage {
+    pub fn render(&self) -> String {
+        let info = self.live.info.read().unwrap();
+        let players = self.live.players.read().unwrap();
+        let states = self.live.states.read().unwrap();
+        let opt_out = self.live.opt_out.read().unwrap();
+        let now = Local::now();
+
+        let up = self.live.servers.iter().filter(|server| states.get(server).map(|state| state.up).unwrap_or(false)).count();
+        let total_players: usize = self.live.servers.iter().filter(|server| states.get(server).map(|state| state.up).unwrap_or(false)).filter_map(|server| players.get(server)).map(Vec::len).sum();
+        let last_scan = match states.values().map(|state| state.last_scan).max() {
+            Some(time) => time.format("%H:%M:%S").to_string(),
+            None => "pending".to_string(),
+        };
+
+        let mut html = String::new();
+        let _ = writeln!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">");
+        let _ = writeln!(html, "<meta http-equiv=\"refresh\" content=\"{}\">", self.refresh_seconds.max(1));
+        let _ = writeln!(html, "<title>Server status ({}/{} up)</title>\n<style>{}</style>\n</head>\n<body>", up, self.live.servers.len(), STYLE);
+        let _ = writeln!(html, "<h1>Server status</h1>");
+        let _ = writeln!(html, "<p class=\"summary\">{}/{} servers up, {} players, last scan {}</p>", up, self.live.servers.len(), total_players, last_scan);
+        let _ = writeln!(html, "<table>\n<tr><th></th><th>Server</th><th>Name</th><th>Map</th><th>Players</th><th>Latency</th><th>Last seen</th></tr>");
+
+        for server in &self.live.servers {
+            let state = states.get(server);
+            let (class, label) = match state {
+                Some(state) if state.up => ("up", "up"),
+                Some(_) => ("down", "down"),
+                None => ("pending", "pending"),
+            };
+            let info = info.get(server);
+            //The scanner keeps the last player list of a server that went down, only show it while up
+            let server_players = players.get(server).filter(|_| class == "up");
+
+            let players_cell = match (info, server_players) {
+                (Some(info), Some(list)) => format!("{}/{}", list.len(), info.max_players),
+                (Some(info), None) => format!("?/{}", info.max_players),
+                _ => String::new(),
+            };
+            let last_seen = match state.and_then(|state| state.last_seen) {
+                Some(time) => format!("{} ago", format_duration((now - time).num_seconds().max(0) as usize)),
+                None => "never".to_string(),
+            };
+
+            let _ = writeln!(
+                html,
+                "<tr class=\"{}\"><td><span class=\"badge\">{}</span></td><td>{}</td><td>{}</td><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+                class,
+                label,
+                server,
+                escape(info.map(|info| info.name.as_str()).unwrap_or("")),
+                escape(info.map(|info| info.map.as_str()).unwrap_or("")),
+                players_cell,
+                state.and_then(|state| state.latency_ms).map(|latency| format!("{}ms", latency)).unwrap_or_default(),
+                last_seen,
+            );
+
+            if let Some(list) = server_players.filter(|list| !list.is_empty()) {
+                let _ = write!(html, "<tr class=\"players\"><td></td><td colspan=\"6\"><details><summary>{} players</summary><table>", list.len());
+                //Opted out players are counted but not named
+                let mut list: Vec<Player> = list.iter().filter(|player| !opt_out.contains(&player.name)).cloned().collect();
+                list.sort_by_key(|player| std::cmp::Reverse(player.score));
+                for player in &list {
+                    let _ = write!(html, "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>", escape(&player.name), player.score, format_duration(player.duration as usize));
+                }
+                let _ = writeln!(html, "</table></details></td></tr>");
+            }
+        }
+
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+.summary { color: #666; }
+table { border-collapse: collapse; }
+th, td { padding: 0.3em 0.8em; text-align: left; border-bottom: 1px solid #eee; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+tr.players td { border-bottom: none; }
+tr.players table td { padding: 0.1em 0.8em; font-size: 0.9em; }
+.badge { display: inline-block; min-width: 4em; text-align: center; border-radius: 3px; color: white; font-size: 0.8em; padding: 0.1em 0.3em; }
+tr.up .badge { background: #2ca02c; }
+tr.down .badge { background: #d62728; }
+tr.pending .badge { background: #999; }
+";
+
+pub fn serve(page: StatusPage, address: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = Server::http(address)?;
+    let content_type = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            //Streams stay open, so each gets its own thread, up to a limit
+            if request.url() == "/events" {
+                match page.live.broadcaster.subscribe_stream() {
+                    Some(subscription) => {thread::spawn(move || events::stream(request, subscription));},
+                    None => {let _ = request.respond(Response::from_string("Too many event streams").with_status_code(503));},
+                }
+                continue;
+            }
+
+            let response = match request.url() {
+                "/" => Response::from_string(page.render()).with_header(content_type.clone()),
+                _ => Response::from_string("Not Found").with_status_code(404),
+            };
+            let _ = request.respond(response);
+        }
+    });
+
+    Ok(())
+}
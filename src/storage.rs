@@ -0,0 +1,390 @@
+use crate::{pgsql, sql};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::Connection;
+use std::{error::Error, fmt};
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Sqlite,
+    Postgres,
+}
+
+//Where the scanner writes, cloned into the pruning thread so it can open its own connection.
+//Sqlite files also carry the zone their old local time strings were written in.
+#[derive(Clone)]
+pub enum Backend {
+    Sqlite(String, Option<Tz>),
+    Postgres(String),
+}
+
+impl Backend {
+    //From the database_backend, postgres_url and legacy_time_zone config keys
+    pub fn new(kind: Kind, db_file: &str, postgres_url: Option<&str>, legacy_time_zone: Option<&str>) -> std::result::Result<Backend, String> {
+        match kind {
+            Kind::Sqlite => match legacy_time_zone.map(str::parse::<Tz>).transpose() {
+                Ok(zone) => Ok(Backend::Sqlite(db_file.to_string(), zone)),
+                Err(_) => Err(format!("Invalid legacy_time_zone ({}), expected a zone name like Europe/Berlin", legacy_time_zone.unwrap_or_default())),
+            },
+            Kind::Postgres => match postgres_url {
+                Some(url) => Ok(Backend::Postgres(url.to_string())),
+                None => Err("database_backend is postgres but postgres_url is not set".to_string()),
+            },
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Storage>> {
+        match self {
+            Backend::Sqlite(path, zone) => Ok(Box::new(SqliteStorage { connection: sql::open(path)?, legacy_time_zone: *zone })),
+            Backend::Postgres(url) => Ok(Box::new(pgsql::PostgresStorage::connect(url)?)),
+        }
+    }
+
+    //For the db commands, a mistyped sqlite path is an error rather than a new empty database
+    pub fn open_existing(&self) -> Result<Box<dyn Storage>> {
+        match self {
+            Backend::Sqlite(path, zone) => Ok(Box::new(SqliteStorage { connection: sql::open_existing(path)?, legacy_time_zone: *zone })),
+            Backend::Postgres(url) => Ok(Box::new(pgsql::PostgresStorage::connect(url)?)),
+        }
+    }
+
+    //For the db commands that only read, nothing is migrated or written
+    pub fn open_read_only(&self) -> Result<Box<dyn Storage>> {
+        match self {
+            Backend::Sqlite(path, zone) => Ok(Box::new(SqliteStorage { connection: sql::open_read_only(path)?, legacy_time_zone: *zone })),
+            Backend::Postgres(url) => Ok(Box::new(pgsql::PostgresStorage::connect_read_only(url)?)),
+        }
+    }
+}
+
+//The postgres url can hold a password, so only the parts needed to tell databases apart are shown
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Sqlite(path, _) => write!(f, "{}", path),
+            Backend::Postgres(url) => write!(f, "{}", pgsql::describe(url)),
+        }
+    }
+}
+
+pub struct Stats {
+    pub tables: Vec<(String, i64)>,
+    pub size_bytes: i64,
+    pub free_bytes: Option<i64>,
+}
+
+//Everything the scanner, the pruner and the db commands need from a database
+pub trait Storage {
+    //Brings the schema up to date, returns the number of migrations applied
+    fn migrate(&mut self) -> Result<usize>;
+    fn schema_version(&mut self) -> Result<(usize, usize)>;
+    fn stats(&mut self) -> Result<Stats>;
+
+    //Groups the writes of one scan, so a failure part way leaves none of them behind
+    fn begin(&mut self) -> Result<()>;
+    fn commit(&mut self) -> Result<()>;
+    fn rollback(&mut self) -> Result<()>;
+
+    fn insert_server(&mut self, address: &str) -> Result<()>;
+    fn get_server_by_addr(&mut self, address: &str) -> Result<Option<sql::Server>>;
+    //Latest settings of a server
+    fn get_server_settings(&mut self, server_id: i32) -> Result<Option<sql::ServerSettings>>;
+    fn insert_server_settings(&mut self, settings: &sql::ServerSettings) -> Result<()>;
+    fn insert_server_event(&mut self, event: &sql::ServerEvent) -> Result<()>;
+    //pseudonyms marks the names as written by a pseudonymiser
+    fn insert_players_batch(&mut self, players: &[sql::Player], pseudonyms: bool) -> Result<()>;
+    fn insert_session(&mut self, name: &str, session: &sql::Session) -> Result<()>;
+    fn insert_player_event(&mut self, name: &str, event: &sql::PlayerEvent) -> Result<()>;
+    fn insert_population_sample(&mut self, sample: &sql::PopulationSample) -> Result<()>;
+
+    //Deletes up to limit rows of table older than cutoff
+    fn delete_batch_before(&mut self, table: &str, column: &str, cutoff: DateTime<Utc>, limit: usize) -> Result<usize>;
+    //Deletes up to limit players no session or player event refers to anymore
+    fn delete_batch_unreferenced_players(&mut self, limit: usize) -> Result<usize>;
+    //Deletes a player with all of their sessions and events, None if the name isn't stored
+    fn delete_player(&mut self, name: &str) -> Result<Option<(usize, usize)>>;
+
+    //Vacuum, backups and the integrity check work on the sqlite file itself
+    fn sqlite(&mut self) -> Option<&mut Connection> {
+        None
+    }
+}
+
+pub struct SqliteStorage {
+    connection: Connection,
+    legacy_time_zone: Option<Tz>,
+}
+
+//Not found is an error in sql, here it is None like the other backends
+fn optional<T>(result: rusqlite::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn migrate(&mut self) -> Result<usize> {
+        Ok(sql::migrate(&mut self.connection, self.legacy_time_zone)?)
+    }
+
+    fn schema_version(&mut self) -> Result<(usize, usize)> {
+        Ok(sql::schema_version(&self.connection)?)
+    }
+
+    fn stats(&mut self) -> Result<Stats> {
+        let stats = sql::get_database_stats(&self.connection)?;
+        Ok(Stats {
+            tables: stats.tables,
+            size_bytes: stats.page_count * stats.page_size,
+            free_bytes: Some(stats.freelist_count * stats.page_size),
+        })
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        Ok(self.connection.execute_batch("BEGIN")?)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(self.connection.execute_batch("COMMIT")?)
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        Ok(self.connection.execute_batch("ROLLBACK")?)
+    }
+
+    fn insert_server(&mut self, address: &str) -> Result<()> {
+        sql::insert_server(&self.connection, &sql::Server { server_id: 0, address: address.to_string() })?;
+        Ok(())
+    }
+
+    fn get_server_by_addr(&mut self, address: &str) -> Result<Option<sql::Server>> {
+        optional(sql::get_server_by_addr(&self.connection, address.to_string()))
+    }
+
+    fn get_server_settings(&mut self, server_id: i32) -> Result<Option<sql::ServerSettings>> {
+        optional(sql::get_server_settings(&self.connection, server_id))
+    }
+
+    fn insert_server_settings(&mut self, settings: &sql::ServerSettings) -> Result<()> {
+        sql::insert_server_settings(&self.connection, settings)?;
+        Ok(())
+    }
+
+    fn insert_server_event(&mut self, event: &sql::ServerEvent) -> Result<()> {
+        Ok(sql::insert_server_event(&self.connection, event)?)
+    }
+
+    fn insert_players_batch(&mut self, players: &[sql::Player], pseudonyms: bool) -> Result<()> {
+        sql::insert_players_batch(&mut self.connection, players, pseudonyms)?;
+        Ok(())
+    }
+
+    fn insert_session(&mut self, name: &str, session: &sql::Session) -> Result<()> {
+        sql::insert_session(&self.connection, &name.to_string(), session)?;
+        Ok(())
+    }
+
+    fn insert_player_event(&mut self, name: &str, event: &sql::PlayerEvent) -> Result<()> {
+        Ok(sql::insert_player_event(&self.connection, &name.to_string(), event)?)
+    }
+
+    fn insert_population_sample(&mut self, sample: &sql::PopulationSample) -> Result<()> {
+        Ok(sql::insert_population_sample(&self.connection, sample)?)
+    }
+
+    fn delete_batch_before(&mut self, table: &str, column: &str, cutoff: DateTime<Utc>, limit: usize) -> Result<usize> {
+        Ok(sql::delete_batch_before(&self.connection, table, column, cutoff, limit)?)
+    }
+
+    fn delete_batch_unreferenced_players(&mut self, limit: usize) -> Result<usize> {
+        Ok(sql::delete_batch_unreferenced_players(&self.connection, limit)?)
+    }
+
+    fn delete_player(&mut self, name: &str) -> Result<Option<(usize, usize)>> {
+        Ok(sql::delete_player(&mut self.connection, name)?)
+    }
+
+    fn sqlite(&mut self) -> Option<&mut Connection> {
+        Some(&mut self.connection)
+    }
+}
+
+//The same cases run against every backend, the postgres ones are ignored by default as they need a database
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "127.0.0.1:27015";
+
+    fn at(millis: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(1_700_000_000_000 + millis).unwrap()
+    }
+
+    fn rows(storage: &mut dyn Storage, table: &str) -> i64 {
+        storage.stats().unwrap().tables.into_iter().find(|(name, _)| name == table).map(|(_, rows)| rows).unwrap()
+    }
+
+    fn settings(server_id: i32, map: &str, created_at: DateTime<Utc>) -> sql::ServerSettings {
+        sql::ServerSettings {
+            setting_id: 0,
+            server_id,
+            name: "test server".to_string(),
+            max_players: 24,
+            current_map: map.to_string(),
+            vac_status: true,
+            has_password: false,
+            game_version: "8835751".to_string(),
+            bots: 0,
+            created_at,
+        }
+    }
+
+    fn session(server_id: i32, left_at: DateTime<Utc>) -> sql::Session {
+        sql::Session { session_id: 0, server_id, player_id: 0, score: 10, duration: 60.0, joined_at: left_at - chrono::Duration::minutes(1), left_at }
+    }
+
+    fn event(server_id: i32, kind: sql::PlayerEventKind, created_at: DateTime<Utc>) -> sql::PlayerEvent {
+        sql::PlayerEvent { event_id: 0, server_id, player_id: 0, kind, created_at }
+    }
+
+    //A migrated database with one server and players a, b and c, returns the server id
+    fn populate(storage: &mut dyn Storage) -> i32 {
+        storage.migrate().unwrap();
+        storage.insert_server(ADDRESS).unwrap();
+        let server_id = storage.get_server_by_addr(ADDRESS).unwrap().unwrap().server_id;
+        let players: Vec<_> = ["a", "b", "c"].iter().map(|name| sql::Player { player_id: 0, name: name.to_string() }).collect();
+        storage.insert_players_batch(&players, false).unwrap();
+        server_id
+    }
+
+    pub fn migrates_once(storage: &mut dyn Storage) {
+        let (_, latest) = storage.schema_version().unwrap();
+        assert_eq!(storage.migrate().unwrap(), latest);
+        assert_eq!(storage.migrate().unwrap(), 0);
+        assert_eq!(storage.schema_version().unwrap(), (latest, latest));
+    }
+
+    pub fn inserts(storage: &mut dyn Storage) {
+        let server_id = populate(storage);
+        storage.insert_server(ADDRESS).unwrap();
+        assert_eq!(rows(storage, "servers"), 1);
+        assert!(storage.get_server_by_addr("127.0.0.1:27016").unwrap().is_none());
+
+        assert!(storage.get_server_settings(server_id).unwrap().is_none());
+        storage.insert_server_settings(&settings(server_id, "pl_upward", at(0))).unwrap();
+        storage.insert_server_settings(&settings(server_id, "cp_badlands", at(1000))).unwrap();
+        let latest = storage.get_server_settings(server_id).unwrap().unwrap();
+        assert_eq!(latest, sql::ServerSettings { setting_id: latest.setting_id, ..settings(server_id, "cp_badlands", at(1000)) });
+
+        let players: Vec<_> = ["a", "d"].iter().map(|name| sql::Player { player_id: 0, name: name.to_string() }).collect();
+        storage.insert_players_batch(&players, true).unwrap();
+        assert_eq!(rows(storage, "players"), 4);
+        assert_eq!(rows(storage, "pseudonyms"), 2);
+
+        storage.insert_session("a", &session(server_id, at(0))).unwrap();
+        storage.insert_player_event("a", &event(server_id, sql::PlayerEventKind::PointChange { score: 5 }, at(0))).unwrap();
+        storage.insert_server_event(&sql::ServerEvent { event_id: 0, server_id, kind: sql::ServerEventKind::SettingChange { map: "cp_badlands".to_string() }, created_at: at(0) }).unwrap();
+        storage.insert_population_sample(&sql::PopulationSample { sample_id: 0, server_id, players: 12, bots: 0, players_joined: 2, players_left: 1, created_at: at(0) }).unwrap();
+        for table in ["sessions", "player_events", "server_events", "population_samples"] {
+            assert_eq!(rows(storage, table), 1, "{}", table);
+        }
+    }
+
+    pub fn deletes_batches_before(storage: &mut dyn Storage) {
+        let server_id = populate(storage);
+        for millis in [0, 1000, 2000] {
+            storage.insert_player_event("a", &event(server_id, sql::PlayerEventKind::Join, at(millis))).unwrap();
+            storage.insert_server_settings(&settings(server_id, "pl_upward", at(millis))).unwrap();
+        }
+        assert_eq!(storage.delete_batch_before("player_events", "created_at", at(2000), 1).unwrap(), 1);
+        assert_eq!(storage.delete_batch_before("player_events", "created_at", at(2000), 5).unwrap(), 1);
+        assert_eq!(storage.delete_batch_before("player_events", "created_at", at(2000), 5).unwrap(), 0);
+        assert_eq!(rows(storage, "player_events"), 1);
+
+        //The latest settings survive even when they are older than the cutoff
+        assert_eq!(storage.delete_batch_before("server_settings", "created_at", at(5000), 5).unwrap(), 2);
+        assert_eq!(storage.get_server_settings(server_id).unwrap().unwrap().created_at, at(2000));
+    }
+
+    pub fn rolls_back(storage: &mut dyn Storage) {
+        let server_id = populate(storage);
+        storage.begin().unwrap();
+        storage.insert_players_batch(&[sql::Player { player_id: 0, name: "d".to_string() }], true).unwrap();
+        storage.insert_session("d", &session(server_id, at(0))).unwrap();
+        storage.rollback().unwrap();
+        assert_eq!(rows(storage, "players"), 3);
+        assert_eq!(rows(storage, "sessions"), 0);
+
+        storage.begin().unwrap();
+        storage.insert_session("a", &session(server_id, at(0))).unwrap();
+        storage.commit().unwrap();
+        assert_eq!(rows(storage, "sessions"), 1);
+    }
+
+    pub fn deletes_unreferenced_players(storage: &mut dyn Storage) {
+        let server_id = populate(storage);
+        storage.insert_session("a", &session(server_id, at(0))).unwrap();
+        storage.insert_player_event("b", &event(server_id, sql::PlayerEventKind::Join, at(0))).unwrap();
+        assert_eq!(storage.delete_batch_unreferenced_players(5).unwrap(), 1);
+        assert_eq!(storage.delete_batch_unreferenced_players(5).unwrap(), 0);
+        assert_eq!(rows(storage, "players"), 2);
+    }
+
+    pub fn deletes_player(storage: &mut dyn Storage) {
+        let server_id = populate(storage);
+        storage.insert_players_batch(&[sql::Player { player_id: 0, name: "a".to_string() }], true).unwrap();
+        storage.insert_session("a", &session(server_id, at(0))).unwrap();
+        storage.insert_session("b", &session(server_id, at(0))).unwrap();
+        for millis in [0, 1000] {
+            storage.insert_player_event("a", &event(server_id, sql::PlayerEventKind::Join, at(millis))).unwrap();
+        }
+        assert_eq!(storage.delete_player("a").unwrap(), Some((1, 2)));
+        assert_eq!(storage.delete_player("a").unwrap(), None);
+        assert_eq!(rows(storage, "players"), 2);
+        assert_eq!(rows(storage, "pseudonyms"), 0);
+        assert_eq!(rows(storage, "sessions"), 1);
+    }
+
+    //A new file as players-empty.db ships it before migrations, in memory
+    fn sqlite() -> SqliteStorage {
+        let connection = sql::open(":memory:").unwrap();
+        connection.execute_batch(include_str!("../db-tools/up.sql")).unwrap();
+        SqliteStorage { connection, legacy_time_zone: None }
+    }
+
+    #[test]
+    fn sqlite_migrates_once() {
+        migrates_once(&mut sqlite());
+    }
+
+    #[test]
+    fn sqlite_inserts() {
+        inserts(&mut sqlite());
+    }
+
+    #[test]
+    fn sqlite_rolls_back() {
+        rolls_back(&mut sqlite());
+    }
+
+    #[test]
+    fn sqlite_deletes_batches_before() {
+        deletes_batches_before(&mut sqlite());
+    }
+
+    #[test]
+    fn sqlite_deletes_unreferenced_players() {
+        deletes_unreferenced_players(&mut sqlite());
+    }
+
+    #[test]
+    fn sqlite_deletes_player() {
+        deletes_player(&mut sqlite());
+    }
+}
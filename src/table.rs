@@ -0,0 +1,187 @@
+use crate::html::escape;
+use std::{fmt, io::{self, Write}, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Csv,
+    Json,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("Unknown format ({}), expected text, csv, json or html", input)),
+        }
+    }
+}
+
+//A single value in a table, kept typed so json output has real numbers
+#[derive(Debug, Clone)]
+pub enum Cell {
+    Text(String),
+    Integer(i64),
+    Float(f64, usize),
+    Empty,
+}
+
+impl Cell {
+    pub fn float(value: f64, precision: usize) -> Cell {
+        Cell::Float(value, precision)
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        match self {
+            Cell::Text(text) => text.as_str().into(),
+            Cell::Integer(number) => (*number).into(),
+            Cell::Float(number, precision) => format!("{:.*}", precision, number).parse::<f64>().unwrap().into(),
+            Cell::Empty => json::JsonValue::Null,
+        }
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cell::Text(text) => write!(f, "{}", text),
+            Cell::Integer(number) => write!(f, "{}", number),
+            Cell::Float(number, precision) => write!(f, "{:.*}", precision, number),
+            Cell::Empty => Ok(()),
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Cell {
+        Cell::Text(text)
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Cell {
+        Cell::Text(text.to_string())
+    }
+}
+
+impl From<i64> for Cell {
+    fn from(number: i64) -> Cell {
+        Cell::Integer(number)
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Cell {
+        value.map(Into::into).unwrap_or(Cell::Empty)
+    }
+}
+
+//Rows of query output, printed as an aligned table or written as csv, json or a html table
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Table {
+        Table {
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Cell>) {
+        self.rows.push(row);
+    }
+
+    pub fn write(&self, format: Format, out: &mut dyn Write) -> io::Result<()> {
+        match format {
+            Format::Text => self.write_text(out),
+            Format::Csv => self.write_csv(out),
+            Format::Json => self.write_json(out),
+            Format::Html => self.write_html(out),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    //Keep only the first rows, for top n lists
+    pub fn truncate(&mut self, rows: usize) {
+        self.rows.truncate(rows);
+    }
+
+    fn write_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let rows: Vec<Vec<String>> = self.rows.iter().map(|row| row.iter().map(Cell::to_string).collect()).collect();
+        let widths: Vec<usize> = (0..self.headers.len())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].chars().count())
+                    .chain([self.headers[column].len()])
+                    .max()
+                    .unwrap()
+            })
+            .collect();
+
+        let line = |row: &[String]| -> String {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+
+        writeln!(out, "{}", line(&self.headers))?;
+        writeln!(out, "{}", widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>().join("  "))?;
+        for row in &rows {
+            writeln!(out, "{}", line(row))?;
+        }
+        Ok(())
+    }
+
+    fn write_csv(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(&self.headers)?;
+        for row in &self.rows {
+            writer.write_record(row.iter().map(Cell::to_string))?;
+        }
+        writer.flush()
+    }
+
+    fn write_json(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut array = json::JsonValue::new_array();
+        for row in &self.rows {
+            let mut object = json::JsonValue::new_object();
+            for (header, cell) in self.headers.iter().zip(row) {
+                object[header.as_str()] = cell.to_json();
+            }
+            array.push(object).unwrap();
+        }
+        writeln!(out, "{}", array.pretty(2))
+    }
+
+    fn write_html(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "<table>")?;
+        writeln!(out, "<tr>{}</tr>", self.headers.iter().map(|header| format!("<th>{}</th>", escape(header))).collect::<String>())?;
+        for row in &self.rows {
+            let cells: String = row
+                .iter()
+                .map(|cell| match cell {
+                    Cell::Integer(_) | Cell::Float(..) => format!("<td class=\"number\">{}</td>", cell),
+                    _ => format!("<td>{}</td>", escape(&cell.to_string())),
+                })
+                .collect();
+            writeln!(out, "<tr>{}</tr>", cells)?;
+        }
+        writeln!(out, "</table>")
+    }
+}
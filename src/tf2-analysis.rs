@@ -1,45 +1,629 @@
-mod sql;
+mod charts;
+mod export;
+mod report;
+mod table;
+
 use argh::FromArgs;
-use rusqlite::Connection;
-use std::{process::exit, time::Instant};
+use chrono::{Local, NaiveDateTime};
+use queries::Filter;
+use std::{fs::{self, File}, io::{self, Write}, process::exit};
+use tf2_surveillance::{database::Database, duration, html, queries, storage::{self, Backend, Result}};
+use table::{Cell, Table};
 
 #[derive(FromArgs)]
 ///Reads and analyses data from a database.
 struct Arguments {
     ///sqlite db path
     #[argh(option, short = 'd')]
-    db_file: String,
+    db_file: Option<String>,
+    ///postgres connection string, read instead of a sqlite file
+    #[argh(option)]
+    postgres_url: Option<String>,
+    ///only include data after this time, eg. 7d, 12h or 2023-11-01
+    #[argh(option, from_str_fn(queries::parse_time))]
+    since: Option<NaiveDateTime>,
+    ///only include data before this time
+    #[argh(option, from_str_fn(queries::parse_time))]
+    until: Option<NaiveDateTime>,
+    ///only include this server (ip:port)
+    #[argh(option, short = 's')]
+    server: Option<String>,
+    ///output format, text, csv, json or html
+    #[argh(option, short = 'f', default = "table::Format::Text")]
+    format: table::Format,
+    ///write the output to a file instead of stdout
+    #[argh(option, short = 'o')]
+    out: Option<String>,
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Servers(ServersCommand),
+    Population(PopulationCommand),
+    Maps(MapsCommand),
+    MapRetention(MapRetentionCommand),
+    PeakHours(PeakHoursCommand),
+    Heatmap(HeatmapCommand),
+    Uptime(UptimeCommand),
+    Versions(VersionsCommand),
+    Chart(ChartCommand),
+    Report(ReportCommand),
+    Export(ExportCommand),
+}
+
+#[derive(FromArgs)]
+///Sessions, players and last seen time of every server
+#[argh(subcommand, name = "servers")]
+struct ServersCommand {}
+
+#[derive(FromArgs)]
+///Average players on each server over time
+#[argh(subcommand, name = "population")]
+struct PopulationCommand {
+    ///bucket size, eg. 15m, 1h or 1d (default 1h)
+    #[argh(option, default = "String::from(\"1h\")")]
+    step: String,
+}
+
+#[derive(FromArgs)]
+///Rounds, hours played and sessions for each map
+#[argh(subcommand, name = "maps")]
+struct MapsCommand {}
+
+#[derive(FromArgs)]
+///Players at map start and end, minutes per player and churn per server and map
+#[argh(subcommand, name = "map-retention")]
+struct MapRetentionCommand {}
+
+#[derive(FromArgs)]
+///Average and peak players for each hour of the day
+#[argh(subcommand, name = "peak-hours")]
+struct PeakHoursCommand {}
+
+#[derive(FromArgs)]
+///Average and peak players on each server for every hour of the week
+#[argh(subcommand, name = "heatmap")]
+struct HeatmapCommand {
+    ///also render the heatmap to this svg file
+    #[argh(option)]
+    svg: Option<String>,
+    ///value shaded in the svg, avg or peak (default avg)
+    #[argh(option, default = "String::from(\"avg\")")]
+    metric: String,
+}
+
+#[derive(FromArgs)]
+///Availability, outages, MTBF and MTTR of every server
+#[argh(subcommand, name = "uptime")]
+struct UptimeCommand {
+    ///gaps between scans longer than this are treated as the scanner being offline (default 5m)
+    #[argh(option, default = "String::from(\"5m\")")]
+    max_gap: String,
+}
+
+#[derive(FromArgs)]
+///Game version changes of every server
+#[argh(subcommand, name = "versions")]
+struct VersionsCommand {}
+
+#[derive(FromArgs)]
+///Draw a chart to an svg or png file
+#[argh(subcommand, name = "chart")]
+struct ChartCommand {
+    ///population, uptime, maps, sessions or heatmap
+    #[argh(positional)]
+    kind: String,
+    ///output file, .svg or .png
+    #[argh(positional)]
+    path: String,
+    ///population bucket size (default 1h)
+    #[argh(option, default = "String::from(\"1h\")")]
+    step: String,
+    ///session length histogram bucket size (default 5m)
+    #[argh(option, default = "String::from(\"5m\")")]
+    bucket: String,
+    ///uptime gaps between scans longer than this are treated as the scanner being offline (default 5m)
+    #[argh(option, default = "String::from(\"5m\")")]
+    max_gap: String,
+}
+
+#[derive(FromArgs)]
+///Write a self contained html report of populations, uptime, maps, busy hours and version changes
+#[argh(subcommand, name = "report")]
+struct ReportCommand {
+    ///only include data after this time, overrides the global --since
+    #[argh(option, from_str_fn(queries::parse_time))]
+    since: Option<NaiveDateTime>,
+    ///only include data before this time, overrides the global --until
+    #[argh(option, from_str_fn(queries::parse_time))]
+    until: Option<NaiveDateTime>,
+    ///html file to write, overrides the global --out
+    #[argh(option)]
+    out: Option<String>,
+    ///page title (default "Server report")
+    #[argh(option, default = "String::from(\"Server report\")")]
+    title: String,
+    ///population bucket size (default 1h)
+    #[argh(option, default = "String::from(\"1h\")")]
+    step: String,
+    ///uptime gaps between scans longer than this are treated as the scanner being offline (default 5m)
+    #[argh(option, default = "String::from(\"5m\")")]
+    max_gap: String,
+}
+
+#[derive(FromArgs)]
+///Stream a table to csv, jsonl or parquet, filtered by --since, --until and --server
+#[argh(subcommand, name = "export")]
+struct ExportCommand {
+    ///servers, server_settings, players, sessions, server_events, player_events or population_samples
+    #[argh(positional)]
+    table: String,
+    ///csv, jsonl or parquet (default from the --out extension, otherwise csv)
+    #[argh(option)]
+    format: Option<export::Format>,
 }
 
 fn main() {
     let args: Arguments = argh::from_env();
 
-    let db_file = args.db_file;
-
-    let mut connection = match Connection::open(db_file.clone()) {
-        Ok(disk_connection) => {
-            println!("DB opened ({})", db_file);
-            disk_connection
+    let backend = match (&args.db_file, &args.postgres_url) {
+        (Some(db_file), None) => Backend::new(storage::Kind::Sqlite, db_file, None, None),
+        (None, Some(postgres_url)) => Backend::new(storage::Kind::Postgres, "", Some(postgres_url), None),
+        _ => Err("Expected either --db-file or --postgres-url".to_string()),
+    };
+    let backend = match backend {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
         }
+    };
+    let mut database = match Database::open(&backend) {
+        Ok(database) => database,
         Err(e) => {
             eprintln!("Failed to establish database connection ({})", e);
             exit(1)
         }
     };
+    if let Err(e) = queries::check_schema(&mut database) {
+        eprintln!("{}", e);
+        exit(1)
+    }
+
+    let filter = Filter {
+        since: args.since,
+        until: args.until,
+        server: args.server.clone(),
+    };
+
+    match &args.command {
+        Command::Chart(command) => chart(&mut database, &filter, command),
+        Command::Report(command) => report(&mut database, &filter, command, args.out.as_deref()),
+        Command::Export(command) => export(&mut database, &filter, command, args.out.as_deref()),
+        Command::Servers(_) => write_table(servers(&mut database, &filter), &args),
+        Command::Population(command) => match duration::parse_duration(&command.step) {
+            Ok(step) if step > 0 => write_table(population(&mut database, &filter, step), &args),
+            _ => {
+                eprintln!("Invalid step ({})", command.step);
+                exit(1)
+            }
+        },
+        Command::Maps(_) => write_table(maps(&mut database, &filter), &args),
+        Command::MapRetention(_) => write_table(map_retention(&mut database, &filter), &args),
+        Command::PeakHours(_) => write_table(peak_hours(&mut database, &filter), &args),
+        Command::Heatmap(command) => write_table(heatmap(&mut database, &filter, command), &args),
+        Command::Versions(_) => write_table(versions(&mut database, &filter), &args),
+        Command::Uptime(command) => match duration::parse_duration(&command.max_gap) {
+            Ok(max_gap) => write_table(uptime(&mut database, &filter, max_gap), &args),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1)
+            }
+        },
+    }
+}
+
+//Prints a query's table to --out or stdout in the chosen format
+fn write_table(result: Result<Table>, args: &Arguments) {
+    let table = match result {
+        Ok(table) => table,
+        Err(e) => {
+            eprintln!("Query failed ({})", e);
+            exit(1)
+        }
+    };
+
+    let written = match &args.out {
+        Some(path) => File::create(path).and_then(|mut file| table.write(args.format, &mut file)),
+        None => table.write(args.format, &mut io::stdout().lock()),
+    };
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("Failed to write output ({})", e);
+            exit(1)
+        }
+        _ => (),
+    }
+    let _ = io::stdout().flush();
+}
+
+fn servers(database: &mut Database, filter: &Filter) -> Result<Table> {
+    let mut table = Table::new(&["server", "name", "map", "sessions", "players", "avg_session_min", "player_hours", "last_seen"]);
+    for server in queries::server_summary(database, filter)? {
+        table.push(vec![
+            server.address.into(),
+            server.name.into(),
+            server.map.into(),
+            server.sessions.into(),
+            server.unique_players.into(),
+            Cell::float(server.avg_session_minutes, 1),
+            Cell::float(server.player_hours, 1),
+            server.last_seen.into(),
+        ]);
+    }
+    Ok(table)
+}
+
+fn population(database: &mut Database, filter: &Filter, step: i64) -> Result<Table> {
+    let mut table = Table::new(&["time", "server", "avg_players", "unique_players"]);
+    for sample in queries::population(database, filter, step)? {
+        table.push(vec![
+            sample.time.format("%Y-%m-%d %H:%M").to_string().into(),
+            sample.address.into(),
+            Cell::float(sample.avg_players, 1),
+            sample.unique_players.into(),
+        ]);
+    }
+    Ok(table)
+}
+
+fn maps(database: &mut Database, filter: &Filter) -> Result<Table> {
+    let mut table = Table::new(&["map", "rounds", "servers", "hours", "sessions"]);
+    for map in queries::map_popularity(database, filter)? {
+        table.push(vec![
+            map.map.into(),
+            map.rounds.into(),
+            map.servers.into(),
+            Cell::float(map.hours, 1),
+            map.sessions.into(),
+        ]);
+    }
+    Ok(table)
+}
+
+fn map_retention(database: &mut Database, filter: &Filter) -> Result<Table> {
+    let mut table = Table::new(&["server", "map", "rounds", "avg_players_start", "avg_players_end", "joined", "left", "avg_min_per_player", "churn_pct"]);
+    for map in queries::map_retention(database, filter)? {
+        table.push(vec![
+            map.address.into(),
+            map.map.into(),
+            map.rounds.into(),
+            Cell::float(map.avg_players_start, 1),
+            Cell::float(map.avg_players_end, 1),
+            map.joined.into(),
+            map.left.into(),
+            Cell::float(map.avg_minutes_per_player, 1),
+            Cell::float(map.churn_rate * 100.0, 1),
+        ]);
+    }
+    Ok(table)
+}
+
+fn peak_hours(database: &mut Database, filter: &Filter) -> Result<Table> {
+    let mut table = Table::new(&["hour", "avg_players", "peak_players"]);
+    for hour in queries::peak_hours(database, filter)? {
+        table.push(vec![
+            format!("{:02}:00", hour.hour).into(),
+            Cell::float(hour.avg_players, 1),
+            Cell::float(hour.peak_players, 1),
+        ]);
+    }
+    Ok(table)
+}
+
+fn heatmap(database: &mut Database, filter: &Filter, command: &HeatmapCommand) -> Result<Table> {
+    let cells = queries::heatmap(database, filter)?;
+
+    if let Some(path) = &command.svg {
+        let peak = match command.metric.as_str() {
+            "avg" => false,
+            "peak" => true,
+            _ => {
+                eprintln!("Unknown metric ({}), expected avg or peak", command.metric);
+                exit(1)
+            }
+        };
+
+        if let Err(e) = fs::write(path, charts::heatmap_svg(&heatmap_chart(&cells, peak))) {
+            eprintln!("Failed to write svg ({})", e);
+            exit(1)
+        }
+    }
+
+    let mut table = Table::new(&["server", "weekday", "hour", "avg_players", "peak_players"]);
+    for cell in cells {
+        table.push(vec![
+            cell.address.into(),
+            charts::WEEKDAYS[cell.weekday as usize].into(),
+            format!("{:02}:00", cell.hour).into(),
+            Cell::float(cell.avg_players, 1),
+            Cell::float(cell.peak_players, 1),
+        ]);
+    }
+    Ok(table)
+}
+
+fn uptime(database: &mut Database, filter: &Filter, max_gap: i64) -> Result<Table> {
+    let mut table = Table::new(&[
+        "server", "availability_pct", "monitored_h", "downtime_min", "outages", "mtbf_h", "mttr_min", "longest_outage_min", "longest_outage_start",
+    ]);
+    for server in queries::uptime(database, filter, max_gap)? {
+        table.push(vec![
+            server.address.into(),
+            Cell::float(server.availability, 2),
+            Cell::float(server.monitored_seconds as f64 / 3600.0, 1),
+            Cell::float(server.downtime_seconds as f64 / 60.0, 1),
+            server.outages.into(),
+            server.mtbf_seconds.map(|seconds| Cell::float(seconds as f64 / 3600.0, 1)).into(),
+            server.mttr_seconds.map(|seconds| Cell::float(seconds as f64 / 60.0, 1)).into(),
+            Cell::float(server.longest_outage_seconds as f64 / 60.0, 1),
+            server.longest_outage_start.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()).into(),
+        ]);
+    }
+    Ok(table)
+}
+
+fn versions(database: &mut Database, filter: &Filter) -> Result<Table> {
+    let mut table = Table::new(&["time", "server", "previous_version", "version"]);
+    for change in queries::version_changes(database, filter)? {
+        table.push(vec![change.time.into(), change.address.into(), change.previous_version.into(), change.version.into()]);
+    }
+    Ok(table)
+}
+
+fn export(database: &mut Database, filter: &Filter, command: &ExportCommand, out: Option<&str>) {
+    let format = command.format.or_else(|| out.and_then(export::Format::from_path)).unwrap_or(export::Format::Csv);
+    let writer: Box<dyn Write + Send> = match out {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("Failed to create {} ({})", path, e);
+                exit(1)
+            }
+        },
+        None => Box::new(io::BufWriter::new(io::stdout())),
+    };
+
+    match export::export(database, &command.table, filter, format, writer) {
+        //Stdout may be the export itself, so the summary goes to stderr
+        Ok(rows) => eprintln!("Exported {} rows from {}", rows, command.table),
+        Err(e) if export::is_broken_pipe(e.as_ref()) => (),
+        Err(e) => {
+            eprintln!("Export failed ({})", e);
+            exit(1)
+        }
+    }
+}
+
+fn chart(database: &mut Database, filter: &Filter, command: &ChartCommand) {
+    let duration = |input: &str| match duration::parse_duration(input) {
+        Ok(duration) if duration > 0 => duration,
+        _ => {
+            eprintln!("Invalid duration ({})", input);
+            exit(1)
+        }
+    };
+
+    let svg = match command.kind.as_str() {
+        "population" => population_chart(database, filter, duration(&command.step)),
+        "uptime" => match duration::parse_duration(&command.max_gap) {
+            Ok(max_gap) => uptime_chart(database, filter, max_gap),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1)
+            }
+        },
+        "maps" => maps_chart(database, filter),
+        "sessions" => sessions_chart(database, filter, duration(&command.bucket)),
+        "heatmap" => queries::heatmap(database, filter).map(|cells| charts::heatmap_svg(&heatmap_chart(&cells, false))),
+        _ => {
+            eprintln!("Unknown chart ({}), expected population, uptime, maps, sessions or heatmap", command.kind);
+            exit(1)
+        }
+    };
+
+    let svg = match svg {
+        Ok(svg) => svg,
+        Err(e) => {
+            eprintln!("Query failed ({})", e);
+            exit(1)
+        }
+    };
+    if let Err(e) = charts::save(&svg, &command.path) {
+        eprintln!("Failed to write chart ({})", e);
+        exit(1)
+    }
+}
 
-    let start = Instant::now();
+fn population_chart(database: &mut Database, filter: &Filter, step: i64) -> Result<String> {
+    let mut chart = charts::LineChart {
+        title: "Average players".to_string(),
+        unit: "players".to_string(),
+        series: Vec::new(),
+    };
+    for sample in queries::population(database, filter, step)? {
+        match chart.series.iter_mut().find(|(address, _)| *address == sample.address) {
+            Some((_, points)) => points.push((sample.time, sample.avg_players)),
+            None => chart.series.push((sample.address, vec![(sample.time, sample.avg_players)])),
+        }
+    }
+    chart.series.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(charts::line_svg(&chart))
+}
+
+fn uptime_chart(database: &mut Database, filter: &Filter, max_gap: i64) -> Result<String> {
+    let reports = queries::uptime(database, filter, max_gap)?;
+    let start = filter.since.or(reports.iter().filter_map(|report| report.first_scan).min());
+    let end = filter.until.or(reports.iter().filter_map(|report| report.last_scan).max());
+
+    let chart = charts::Timeline {
+        title: "Server availability".to_string(),
+        start: start.unwrap_or_default(),
+        end: end.unwrap_or_default(),
+        rows: reports
+            .into_iter()
+            .map(|report| charts::TimelineRow {
+                label: format!("{} ({:.2}%)", report.address, report.availability),
+                monitored: (report.first_scan.unwrap_or_default(), report.last_scan.unwrap_or_default()),
+                outages: report.outage_periods.iter().map(|(start, length)| (*start, *start + chrono::Duration::seconds(*length))).collect(),
+            })
+            .collect(),
+    };
+    Ok(charts::timeline_svg(&chart))
+}
 
-    let mut sessions = sql::get_all_sessions(&connection).unwrap();
-    println!("({:?}) Sessions: {}", start.elapsed(), sessions.len());
-    let mut server_events = sql::get_all_server_events(&connection).unwrap();
-    println!("({:?}) Server Events: {}", start.elapsed(), server_events.len());
-    let mut player_events = sql::get_all_player_events(&connection).unwrap();
-    println!("({:?}) Player Events: {}", start.elapsed(), player_events.len());
+fn maps_chart(database: &mut Database, filter: &Filter) -> Result<String> {
+    let chart = charts::BarChart {
+        title: "Hours played per map".to_string(),
+        unit: "hours".to_string(),
+        bars: queries::map_popularity(database, filter)?.into_iter().take(25).map(|map| (map.map, map.hours)).collect(),
+    };
+    Ok(charts::bar_svg(&chart))
+}
 
-    println!("{:?}", sessions.get(sessions.len()-1).unwrap());
+fn sessions_chart(database: &mut Database, filter: &Filter, bucket: i64) -> Result<String> {
+    //Up to 4 hours of sessions, longer ones land in the last column
+    let buckets = (4 * 3600 / bucket).clamp(1, 96);
+    let chart = charts::ColumnChart {
+        title: "Session length".to_string(),
+        unit: "sessions".to_string(),
+        columns: queries::session_lengths(database, filter, bucket, buckets)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, (start, count))| {
+                let label = match index as i64 == buckets - 1 {
+                    true => format!("{}m+", start / 60),
+                    false => format!("{}m", start / 60),
+                };
+                (label, count as f64)
+            })
+            .collect(),
+    };
+    Ok(charts::column_svg(&chart))
+}
 
-    loop {
-        
+fn heatmap_chart(cells: &[queries::HeatmapCell], peak: bool) -> charts::Heatmap {
+    let mut heatmap = charts::Heatmap {
+        title: format!("{} players by hour of week", if peak { "Peak" } else { "Average" }),
+        unit: "players".to_string(),
+        servers: Vec::new(),
+    };
+    for cell in cells {
+        if heatmap.servers.last().map(|(address, _)| *address != cell.address).unwrap_or(true) {
+            heatmap.servers.push((cell.address.clone(), [[0.0; 24]; 7]));
+        }
+        let grid = &mut heatmap.servers.last_mut().unwrap().1;
+        grid[cell.weekday as usize][cell.hour as usize] = if peak { cell.peak_players } else { cell.avg_players };
     }
+    heatmap
+}
 
+fn report(database: &mut Database, filter: &Filter, command: &ReportCommand, out: Option<&str>) {
+    let filter = Filter {
+        since: command.since.or(filter.since),
+        until: command.until.or(filter.until),
+        server: filter.server.clone(),
+    };
+    let step = match duration::parse_duration(&command.step) {
+        Ok(step) if step > 0 => step,
+        _ => {
+            eprintln!("Invalid step ({})", command.step);
+            exit(1)
+        }
+    };
+    let max_gap = match duration::parse_duration(&command.max_gap) {
+        Ok(max_gap) => max_gap,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    };
+
+    let format = |time: NaiveDateTime| time.format("%Y-%m-%d %H:%M").to_string();
+    let period = match (filter.since, filter.until) {
+        (Some(since), Some(until)) => format!("{} to {}", format(since), format(until)),
+        (Some(since), None) => format!("Since {}", format(since)),
+        (None, Some(until)) => format!("Until {}", format(until)),
+        (None, None) => "All recorded data".to_string(),
+    };
+
+    let mut sections = || -> Result<Vec<report::Section>> {
+        let mut maps_table = maps(database, &filter)?;
+        maps_table.truncate(25);
+
+        Ok(vec![
+            report::Section {
+                title: "Servers".to_string(),
+                description: String::new(),
+                charts: Vec::new(),
+                tables: vec![servers(database, &filter)?],
+            },
+            report::Section {
+                title: "Population".to_string(),
+                description: "Average players on each server, estimated from recorded sessions.".to_string(),
+                charts: vec![population_chart(database, &filter, step)?],
+                tables: Vec::new(),
+            },
+            report::Section {
+                title: "Busiest hours".to_string(),
+                description: String::new(),
+                charts: vec![charts::heatmap_svg(&heatmap_chart(&queries::heatmap(database, &filter)?, false))],
+                tables: vec![peak_hours(database, &filter)?],
+            },
+            report::Section {
+                title: "Uptime".to_string(),
+                description: format!("Gaps of more than {} between scans are counted as the scanner being offline.", command.max_gap),
+                charts: vec![uptime_chart(database, &filter, max_gap)?],
+                tables: vec![uptime(database, &filter, max_gap)?],
+            },
+            report::Section {
+                title: "Maps played".to_string(),
+                description: String::new(),
+                charts: vec![maps_chart(database, &filter)?],
+                tables: vec![maps_table],
+            },
+            report::Section {
+                title: "Version changes".to_string(),
+                description: String::new(),
+                charts: Vec::new(),
+                tables: vec![versions(database, &filter)?],
+            },
+        ])
+    };
+
+    let report = report::Report {
+        title: command.title.clone(),
+        period,
+        generated: Local::now().format("%Y-%m-%d %H:%M").to_string(),
+        sections: match sections() {
+            Ok(sections) => sections,
+            Err(e) => {
+                eprintln!("Query failed ({})", e);
+                exit(1)
+            }
+        },
+    };
+
+    let html = report::render(&report);
+    let written = match command.out.as_deref().or(out) {
+        Some(path) => fs::write(path, html),
+        None => io::stdout().lock().write_all(html.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("Failed to write report ({})", e);
+        exit(1)
+    }
 }
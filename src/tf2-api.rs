@@ -0,0 +1,307 @@
+#[macro_use]
+extern crate json;
+
+use argh::FromArgs;
+use chrono::{Duration, Local, Utc};
+use json::JsonValue;
+use queries::{Filter, Page};
+use std::{process::exit, sync::Arc, thread};
+use tf2_surveillance::{database::Database, duration, queries, storage::{self, Backend}};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+const MAX_BUCKETS: i64 = 5000;
+
+#[derive(FromArgs)]
+///Serves the database as a read-only JSON API.
+struct Arguments {
+    ///sqlite db path
+    #[argh(option, short = 'd')]
+    db_file: Option<String>,
+    ///postgres connection string, served instead of a sqlite file
+    #[argh(option)]
+    postgres_url: Option<String>,
+    ///address to listen on (default 127.0.0.1:8080)
+    #[argh(option, short = 'a', default = "String::from(\"127.0.0.1:8080\")")]
+    address: String,
+    ///servers without an up or down scan for this long are reported as unknown (default 5m)
+    #[argh(option, default = "String::from(\"5m\")")]
+    stale_after: String,
+    ///number of worker threads (default 4)
+    #[argh(option, default = "4")]
+    threads: usize,
+}
+
+//A failed request, turned into a json error body with this status code
+struct ApiError(u16, String);
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for ApiError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> ApiError {
+        eprintln!("Query failed ({})", error);
+        ApiError(500, "Query failed".to_string())
+    }
+}
+
+fn main() {
+    let args: Arguments = argh::from_env();
+
+    let backend = match (&args.db_file, &args.postgres_url) {
+        (Some(db_file), None) => Backend::new(storage::Kind::Sqlite, db_file, None, None),
+        (None, Some(postgres_url)) => Backend::new(storage::Kind::Postgres, "", Some(postgres_url), None),
+        _ => Err("Expected either --db-file or --postgres-url".to_string()),
+    };
+    let backend = match backend {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    };
+
+    let stale_after = match duration::parse_duration(&args.stale_after) {
+        Ok(seconds) => seconds,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    };
+
+    let server = match Server::http(&args.address) {
+        Ok(server) => Arc::new(server),
+        Err(e) => {
+            eprintln!("Failed to listen on {} ({})", args.address, e);
+            exit(1)
+        }
+    };
+    println!("Serving {} on http://{}/api", backend, args.address);
+
+    //Every worker gets its own read-only connection, both databases handle the concurrent readers
+    let workers: Vec<_> = (0..args.threads.max(1))
+        .map(|_| {
+            let mut database = match Database::open(&backend) {
+                Ok(database) => database,
+                Err(e) => {
+                    eprintln!("Failed to establish database connection ({})", e);
+                    exit(1)
+                }
+            };
+            if let Err(e) = queries::check_schema(&mut database) {
+                eprintln!("{}", e);
+                exit(1)
+            }
+            let server = Arc::clone(&server);
+            let backend = backend.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    //A restarted postgres server closes the connection for good, open a new one
+                    if database.is_closed() {
+                        match Database::open(&backend) {
+                            Ok(reopened) => database = reopened,
+                            Err(e) => eprintln!("Failed to reconnect to the database ({})", e),
+                        }
+                    }
+                    handle(&mut database, request, stale_after);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle(database: &mut Database, request: Request, stale_after: i64) {
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    let params: Vec<(String, String)> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect();
+
+    let result = match (request.method(), path.trim_end_matches('/')) {
+        (Method::Get, "/api/servers") => servers(database, &params),
+        (Method::Get, "/api/status") => status(database, &params, stale_after),
+        (Method::Get, "/api/settings") => settings(database, &params),
+        (Method::Get, "/api/population") => population(database, &params),
+        (Method::Get, "/api/rounds") => rounds(database, &params),
+        (Method::Get, _) => Err(ApiError(404, format!("Unknown endpoint ({})", path))),
+        _ => Err(ApiError(405, "Only GET is supported".to_string())),
+    };
+
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err(ApiError(status, message)) => (status, object! { "error": message }),
+    };
+
+    let response = Response::from_string(body.dump())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+        .with_header(Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap());
+    let _ = request.respond(response);
+}
+
+fn servers(database: &mut Database, params: &[(String, String)]) -> Result<JsonValue, ApiError> {
+    let mut data = JsonValue::new_array();
+    for server in queries::server_summary(database, &filter(params)?)? {
+        let _ = data.push(object! {
+            "address": server.address,
+            "name": server.name,
+            "map": server.map,
+            "sessions": server.sessions,
+            "unique_players": server.unique_players,
+            "avg_session_minutes": server.avg_session_minutes,
+            "player_hours": server.player_hours,
+            "last_seen": server.last_seen,
+        });
+    }
+    Ok(object! { "data": data })
+}
+
+fn status(database: &mut Database, params: &[(String, String)], stale_after: i64) -> Result<JsonValue, ApiError> {
+    let stale = Utc::now() - Duration::seconds(stale_after);
+
+    let mut data = JsonValue::new_array();
+    for server in queries::server_status(database, &filter(params)?)? {
+        //A scanner that stopped writing says nothing about the server, so old scans are unknown
+        let state = match (&server.last_state, &server.last_scan) {
+            (Some(state), Some(time)) if *time >= stale => state.as_str(),
+            _ => "unknown",
+        };
+        let _ = data.push(object! {
+            "address": server.address,
+            "status": state,
+            "last_scan": server.last_scan.map(|time| time.with_timezone(&Local).format(TIME_FORMAT).to_string()),
+            "name": server.name,
+            "map": server.map,
+            "players": if state == "up" { Some(server.players) } else { None },
+            "max_players": server.max_players,
+            "bots": server.bots,
+            "has_password": server.has_password,
+            "vac_status": server.vac_status,
+            "game_version": server.game_version,
+        });
+    }
+    Ok(object! { "data": data })
+}
+
+fn settings(database: &mut Database, params: &[(String, String)]) -> Result<JsonValue, ApiError> {
+    let page = page(params)?;
+    let mut data = JsonValue::new_array();
+    for settings in queries::settings_history(database, &filter(params)?, page)? {
+        let _ = data.push(object! {
+            "address": settings.address,
+            "time": settings.time,
+            "name": settings.name,
+            "map": settings.map,
+            "max_players": settings.max_players,
+            "bots": settings.bots,
+            "has_password": settings.has_password,
+            "vac_status": settings.vac_status,
+            "game_version": settings.game_version,
+        });
+    }
+    Ok(paginated(data, page))
+}
+
+fn population(database: &mut Database, params: &[(String, String)]) -> Result<JsonValue, ApiError> {
+    let mut filter = filter(params)?;
+    let step = match param(params, "step") {
+        Some(step) => duration::parse_duration(step).map_err(|e| ApiError(400, e))?,
+        None => 3600,
+    };
+    if step <= 0 {
+        return Err(ApiError(400, "step must be positive".to_string()));
+    }
+
+    //Default to the last day so an unfiltered request doesn't bucket the whole database
+    let until = filter.until.unwrap_or_else(|| Local::now().naive_local());
+    let since = *filter.since.get_or_insert(until - Duration::days(1));
+    if (until - since).num_seconds() / step > MAX_BUCKETS {
+        return Err(ApiError(400, format!("Too many buckets, use a larger step or a shorter range (max {})", MAX_BUCKETS)));
+    }
+    filter.until = Some(until);
+
+    let mut data = JsonValue::new_array();
+    for sample in queries::population(database, &filter, step)? {
+        let _ = data.push(object! {
+            "address": sample.address,
+            "time": sample.time.format(TIME_FORMAT).to_string(),
+            "avg_players": sample.avg_players,
+            "unique_players": sample.unique_players,
+        });
+    }
+    Ok(object! { "step": step, "data": data })
+}
+
+fn rounds(database: &mut Database, params: &[(String, String)]) -> Result<JsonValue, ApiError> {
+    let page = page(params)?;
+    let mut data = JsonValue::new_array();
+    for round in queries::map_rounds(database, &filter(params)?, page)? {
+        let _ = data.push(object! {
+            "address": round.address,
+            "map": round.map,
+            "started_at": round.started_at,
+            "ended_at": round.ended_at,
+            "minutes": round.minutes,
+            "sessions": round.sessions,
+        });
+    }
+    Ok(paginated(data, page))
+}
+
+fn paginated(data: JsonValue, page: Page) -> JsonValue {
+    let next_offset = match data.len() as i64 == page.limit {
+        true => Some(page.offset + page.limit),
+        false => None,
+    };
+    object! { "limit": page.limit, "offset": page.offset, "next_offset": next_offset, "data": data }
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+fn filter(params: &[(String, String)]) -> Result<Filter, ApiError> {
+    let time = |name| param(params, name).map(queries::parse_time).transpose().map_err(|e| ApiError(400, e));
+    Ok(Filter {
+        since: time("since")?,
+        until: time("until")?,
+        server: param(params, "server").map(str::to_string),
+    })
+}
+
+fn page(params: &[(String, String)]) -> Result<Page, ApiError> {
+    let number = |name, default| match param(params, name) {
+        Some(value) => value.parse::<i64>().ok().filter(|number| *number >= 0).ok_or(ApiError(400, format!("Invalid {} ({})", name, value))),
+        None => Ok(default),
+    };
+    Ok(Page {
+        limit: number("limit", DEFAULT_LIMIT)?.clamp(1, MAX_LIMIT),
+        offset: number("offset", 0)?,
+    })
+}
+
+//Percent decoding for query strings, + is a space
+fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let hex = |index: usize| bytes.get(index..index + 2).and_then(|pair| std::str::from_utf8(pair).ok()).and_then(|pair| u8::from_str_radix(pair, 16).ok());
+
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match (bytes[index], hex(index + 1)) {
+            (b'+', _) => decoded.push(b' '),
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 2;
+            }
+            (byte, _) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -1,16 +1,21 @@
-mod sql;
+mod metrics;
+mod heartbeat;
+mod logging;
+mod commands;
+mod status;
+mod events;
+mod tui;
+mod retention;
+mod scores;
 
-use rusqlite::Connection;
-use chrono::{Local, NaiveDateTime};
+use chrono::{DateTime, Local, Utc};
+use log::{error, info, log, warn, Level};
 use a2s::{info::Info, A2SClient};
 use std::{collections::HashMap, fs::{self, read_to_string}, net::SocketAddr, process::exit, thread::sleep, time::{Duration, Instant}};
 use argh::FromArgs;
-use std::sync::{Arc, RwLock, atomic::{Ordering, AtomicUsize}};
+use std::sync::{Arc, Mutex, RwLock, atomic::{Ordering, AtomicUsize}};
 use rayon::{prelude::*, ThreadPoolBuilder};
-
-//If using heartbeat with uptimekuma, this will append the tf2 scan total latency at the end of the request
-//If using heartbeat with a service other than uptimekuma change to false.
-const UPTIMEKUMA_PING: bool = true;
+use tf2_surveillance::{duration, html, pseudonym, sql, storage};
 
 #[macro_use]
 extern crate json;
@@ -19,7 +24,7 @@ extern crate json;
 ///Scan and report information from a dedicated tf2 server
 struct Arguments {
     ///config file
-    #[argh(option, short = 'c')]
+    #[argh(option, short = 'c', default = "String::from(\"/etc/tf2-surveillance/config.toml\")")]
     config_file: String,
     ///sqlite db path
     #[argh(option, short = 'd')]
@@ -32,7 +37,135 @@ struct Arguments {
     target_file: Option<String>,
     ///print all leave/join events
     #[argh(switch, short = 'm')]
-    monitor: bool,    
+    monitor: bool,
+    ///displays extra information
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Run(RunCommand),
+    Tui(TuiCommand),
+    CheckConfig(CheckConfigCommand),
+    Query(QueryCommand),
+    Pseudonym(PseudonymCommand),
+    Db(DbCommand),
+}
+
+#[derive(FromArgs)]
+///Scan the target servers until stopped (default)
+#[argh(subcommand, name = "run")]
+struct RunCommand {}
+
+#[derive(FromArgs)]
+///Scan the target servers with a live dashboard instead of log output
+#[argh(subcommand, name = "tui")]
+struct TuiCommand {}
+
+#[derive(FromArgs)]
+///Validate the config and server list, then query every server once
+#[argh(subcommand, name = "check-config")]
+struct CheckConfigCommand {}
+
+#[derive(FromArgs)]
+///Print the info, players and rules of a single server
+#[argh(subcommand, name = "query")]
+struct QueryCommand {
+    ///server address (ip:port)
+    #[argh(positional)]
+    address: String,
+}
+
+#[derive(FromArgs)]
+///Print the pseudonym a player name is stored as
+#[argh(subcommand, name = "pseudonym")]
+struct PseudonymCommand {
+    ///player name
+    #[argh(positional)]
+    name: String,
+}
+
+#[derive(FromArgs)]
+///Database maintenance
+#[argh(subcommand, name = "db")]
+struct DbCommand {
+    #[argh(subcommand)]
+    command: DbSubCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum DbSubCommand {
+    Migrate(DbMigrateCommand),
+    Stats(DbStatsCommand),
+    Vacuum(DbVacuumCommand),
+    Analyze(DbAnalyzeCommand),
+    Check(DbCheckCommand),
+    Backup(DbBackupCommand),
+    Prune(DbPruneCommand),
+    Pseudonymise(DbPseudonymiseCommand),
+    PurgePlayer(DbPurgePlayerCommand),
+}
+
+#[derive(FromArgs)]
+///Apply pending schema changes without starting the scanner
+#[argh(subcommand, name = "migrate")]
+struct DbMigrateCommand {}
+
+#[derive(FromArgs)]
+///Print row counts and file size
+#[argh(subcommand, name = "stats")]
+struct DbStatsCommand {}
+
+#[derive(FromArgs)]
+///Rebuild the database file to reclaim free space
+#[argh(subcommand, name = "vacuum")]
+struct DbVacuumCommand {}
+
+#[derive(FromArgs)]
+///Update the statistics the query planner uses to pick indexes
+#[argh(subcommand, name = "analyze")]
+struct DbAnalyzeCommand {}
+
+#[derive(FromArgs)]
+///Run sqlite's integrity check and look for rows pointing at missing servers or players
+#[argh(subcommand, name = "check")]
+struct DbCheckCommand {}
+
+#[derive(FromArgs)]
+///Copy the database to a file, safe while the scanner is running
+#[argh(subcommand, name = "backup")]
+struct DbBackupCommand {
+    ///backup file to write
+    #[argh(positional)]
+    path: String,
+}
+
+#[derive(FromArgs)]
+///Delete rows older than the configured retention windows once
+#[argh(subcommand, name = "prune")]
+struct DbPruneCommand {}
+
+#[derive(FromArgs)]
+///Replace the plain player names already in the database with pseudonyms
+#[argh(subcommand, name = "pseudonymise")]
+struct DbPseudonymiseCommand {
+    ///keep names of 32 lowercase hex characters, for pseudonyms written before the database tracked them
+    #[argh(switch)]
+    keep_hashed: bool,
+}
+
+#[derive(FromArgs)]
+///Delete a player and their sessions and events
+#[argh(subcommand, name = "purge-player")]
+struct DbPurgePlayerCommand {
+    ///player name, as shown in game
+    #[argh(positional)]
+    name: String,
 }
 
 #[macro_use]
@@ -46,9 +179,44 @@ struct Config {
     refresh_delay: u64,
     heartbeat_enabled: bool,
     heartbeat_url: String,
+    #[serde(default)]
+    heartbeat_method: heartbeat::Method,
+    heartbeat_body: Option<String>,
+    #[serde(default)]
+    heartbeat_failure_threshold: usize,
+    heartbeat_down_url: Option<String>,
     database_file: String,
+    #[serde(default)]
+    database_backend: storage::Kind,
+    postgres_url: Option<String>,
+    legacy_time_zone: Option<String>,
     server_file: String,
     target_file: String,
+    #[serde(default = "default_log_filter")]
+    log_filter: String,
+    #[serde(default)]
+    log_format: logging::Format,
+    #[serde(default)]
+    metrics_enabled: bool,
+    #[serde(default = "default_metrics_address")]
+    metrics_address: String,
+    #[serde(default)]
+    status_enabled: bool,
+    #[serde(default = "default_status_address")]
+    status_address: String,
+    retention_player_events: Option<String>,
+    retention_sessions: Option<String>,
+    retention_server_events: Option<String>,
+    retention_server_settings: Option<String>,
+    retention_population_samples: Option<String>,
+    #[serde(default = "default_retention_interval")]
+    retention_interval: String,
+    pseudonym_secret: Option<String>,
+    #[serde(default)]
+    aggregate_only: bool,
+    #[serde(default = "default_score_sample_interval")]
+    score_sample_interval: String,
+    opt_out_file: Option<String>,
 }
 
 #[derive(Clone)]
@@ -58,11 +226,24 @@ struct Player {
     duration: f32,
 }
 
+//Players of a server that answered this scan, written instead of player data when aggregate only
+struct PopulationCount {
+    server: SocketAddr,
+    players: usize,
+    bots: u8,
+    joined: usize,
+    left: usize,
+}
+
+//What a scan found out about a server, written to the database and streamed to the event clients
 #[derive(Debug)]
 enum ServerEvent {
-    ServerUp(String),
-    ServerDown(String),
-    Settings(String, Info)
+    //Current info and query latency in ms
+    ServerUp(String, Box<Info>, u128),
+    //Why the query failed
+    ServerDown(String, String),
+    //New settings and the ones they replace, None on the first scan since startup
+    Settings(String, Box<Info>, Option<Box<Info>>)
 }
 
 enum PlayerEvent {
@@ -73,94 +254,475 @@ enum PlayerEvent {
     PointUpdate(Player, usize)
 }
 
+impl PlayerEvent {
+    fn player(&self) -> &Player {
+        match self {
+            PlayerEvent::PlayerJoined(player) | PlayerEvent::PlayerLeft(player) | PlayerEvent::TargetJoined(player) | PlayerEvent::TargetLeft(player) | PlayerEvent::PointUpdate(player, _) => player,
+        }
+    }
+}
+
 
 fn main() {
 
     let args: Arguments = argh::from_env();
-    let config = load_config(args.config_file);
+    let config = match load_config(&args.config_file) {
+        Ok(config) => config,
+        Err(e) => {eprintln!("{}", e);exit(1)},
+    };
+
+    //Verbose only raises our own modules to debug so dependencies stay quiet, the dashboard owns the terminal so logging is off
+    let log_filter = match (&args.command, args.verbose) {
+        (Some(Command::Tui(_)), _) => "off".to_string(),
+        (_, true) => format!("{},tf2_scan=debug", config.log_filter),
+        (_, false) => config.log_filter.clone(),
+    };
+    if let Err(e) = logging::init(&log_filter, config.log_format) {
+        eprintln!("Failed to initialise logging ({})", e);
+        exit(1)
+    }
+
+    let db_file = args.db_file.clone().unwrap_or(config.database_file.clone());
+    let server_file = args.server_file.clone().unwrap_or(config.server_file.clone());
+    let target_file = args.target_file.clone().unwrap_or(config.target_file.clone());
+
+    match &args.command {
+        None | Some(Command::Run(_)) => {
+            if let Err(e) = run(&args, &config, &db_file, &target_file, &status::LiveState::new(load_servers(&server_file))) {
+                error!("{}", e);
+                exit(1)
+            }
+        },
+        Some(Command::Tui(_)) => {
+            let live = status::LiveState::new(load_servers(&server_file));
+            //The scan loop only returns when it fails to start, the dashboard then closes so the error can be printed to a restored terminal
+            std::thread::scope(|scope| {
+                scope.spawn(|| if let Err(e) = run(&args, &config, &db_file, &target_file, &live) {
+                    *live.stopped.write().unwrap() = Some(e);
+                });
+                let result = tui::run(&live, config.refresh_delay);
+                if let Some(e) = live.stopped.read().unwrap().as_ref() {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+                exit(match result {
+                    Ok(_) => 0,
+                    Err(e) => {eprintln!("Dashboard failed ({})", e); 1},
+                })
+            })
+        },
+        Some(Command::CheckConfig(_)) => commands::check_config(&config, &db_file, &server_file, &target_file),
+        Some(Command::Query(query)) => commands::query(&query.address),
+        Some(Command::Pseudonym(pseudonym)) => commands::pseudonym(&config, &pseudonym.name),
+        Some(Command::Db(db)) => {
+            let backend = match database_backend(&config, &db_file) {
+                Ok(backend) => backend,
+                Err(e) => {eprintln!("{}", e);exit(1)},
+            };
+            //Reading commands leave the database as they found it, so a backup taken before an upgrade is still the old schema
+            let read_only = matches!(db.command, DbSubCommand::Stats(_) | DbSubCommand::Check(_) | DbSubCommand::Backup(_));
+            let opened = match read_only {
+                true => backend.open_read_only(),
+                false => backend.open_existing(),
+            };
+            let mut storage = match opened {
+                Ok(storage) => storage,
+                Err(e) => {eprintln!("Failed to open database ({}) ({})", backend, e);exit(1)},
+            };
+            let applied = match read_only {
+                true => 0,
+                false => match storage.migrate() {
+                    Ok(applied) => applied,
+                    Err(e) => {eprintln!("Failed to migrate database ({})", e);exit(1)},
+                },
+            };
+            match &db.command {
+                DbSubCommand::Migrate(_) => commands::db_migrate(storage.as_mut(), applied),
+                DbSubCommand::Stats(_) => commands::db_stats(storage.as_mut(), &backend),
+                DbSubCommand::Vacuum(_) => commands::db_vacuum(sqlite_only(storage.as_mut(), "vacuum"), &db_file),
+                DbSubCommand::Analyze(_) => commands::db_analyze(sqlite_only(storage.as_mut(), "analyze")),
+                DbSubCommand::Check(_) => commands::db_check(sqlite_only(storage.as_mut(), "check"), &db_file),
+                DbSubCommand::Backup(backup) => commands::db_backup(sqlite_only(storage.as_mut(), "backup"), &backup.path),
+                DbSubCommand::Prune(_) => commands::db_prune(storage.as_mut(), &config),
+                DbSubCommand::Pseudonymise(pseudonymise) => commands::db_pseudonymise(sqlite_only(storage.as_mut(), "pseudonymise"), &config, pseudonymise.keep_hashed),
+                DbSubCommand::PurgePlayer(purge) => commands::db_purge_player(storage.as_mut(), &config, &purge.name),
+            }
+        },
+    }
+}
 
-    let db_file = args.db_file.unwrap_or(config.database_file);
+//What a scan writes to the database
+struct ScanWrites<'a> {
+    servers: &'a [SocketAddr],
+    server_events: &'a HashMap<SocketAddr, Vec<ServerEvent>>,
+    population: &'a [PopulationCount],
+    players: &'a HashMap<SocketAddr, Vec<Player>>,
+    player_events: &'a HashMap<SocketAddr, Vec<PlayerEvent>>,
+    opt_out: &'a [String],
+}
+
+//Writes one scan in a transaction, returns the number of events written or the first error, after which the whole scan is rolled back
+fn write_scan(storage: &mut dyn storage::Storage, scan: &ScanWrites, config: &Config, pseudonymiser: Option<&pseudonym::Pseudonymiser>, timeline: &mut scores::Timeline) -> storage::Result<u64> {
+    storage.begin()?;
+    let result = write_scan_rows(storage, scan, config, pseudonymiser, timeline).and_then(|event_count| storage.commit().map(|_| event_count));
+    if result.is_err() {
+        //The connection may be gone, it is reopened either way
+        let _ = storage.rollback();
+    }
+    result
+}
+
+fn write_scan_rows(storage: &mut dyn storage::Storage, scan: &ScanWrites, config: &Config, pseudonymiser: Option<&pseudonym::Pseudonymiser>, timeline: &mut scores::Timeline) -> storage::Result<u64> {
+    let mut event_count = 0;
+
+    for address in scan.servers {
+        storage.insert_server(&address.to_string())?;
+        event_count += 1;
+    }
+
+    for server_events in scan.server_events.iter() {
+        let server_id = match storage.get_server_by_addr(&server_events.0.to_string())? {
+            Some(server) => server,
+            None => continue,
+        };
+        for event in server_events.1{
+            match event {
+                ServerEvent::ServerUp(..) => {
+                    storage.insert_server_event(&sql::ServerEvent { event_id: 0, server_id: server_id.server_id, kind: sql::ServerEventKind::Up, created_at: Utc::now() })?;
+                },
+                ServerEvent::ServerDown(..) => {
+                    storage.insert_server_event(&sql::ServerEvent { event_id: 0, server_id: server_id.server_id, kind: sql::ServerEventKind::Down, created_at: Utc::now() })?;
+                },
+                ServerEvent::Settings(_address, info, _previous) => {
+
+                    let mut new_settings = sql::ServerSettings { 
+                        setting_id: 0, 
+                        server_id: server_id.server_id, 
+                        name: info.name.to_string(), 
+                        max_players: info.max_players as i32, 
+                        current_map: info.map.to_string(), 
+                        vac_status: info.vac, 
+                        has_password: info.visibility, 
+                        game_version: info.version.to_string(), 
+                        bots: info.bots,
+                        created_at: DateTime::from_timestamp(1, 0).unwrap() //temporary unix time 1;
+                    };
+
+                    //Read from the database to check if settings have changed or just been dropped from memory (program restart)
+                    match storage.get_server_settings(server_id.server_id)? {
+                        Some(mut previous_settings) => {
+                            previous_settings.created_at = DateTime::from_timestamp(1, 0).unwrap();
+                            previous_settings.setting_id = 0;
+                            if previous_settings != new_settings {
+                                new_settings.created_at = Utc::now();
+                                storage.insert_server_event(&sql::ServerEvent { event_id: 0, server_id: server_id.server_id, kind: sql::ServerEventKind::SettingChange { map: info.map.to_string() }, created_at: Utc::now() })?;
+                                storage.insert_server_settings(&new_settings)?;
+                                event_count += 1;
+                            }
+                        },
+                        None => {
+                            new_settings.created_at = Utc::now();
+                            storage.insert_server_event(&sql::ServerEvent { event_id: 0, server_id: server_id.server_id, kind: sql::ServerEventKind::SettingChange { map: info.map.to_string() }, created_at: Utc::now() })?;
+                            storage.insert_server_settings(&new_settings)?;
+                            event_count += 1;
+                        },
+                    }
+                },
+            }
+            event_count += 1;
+        }
+    }
+
+    //With a secret configured only the keyed hash of a name reaches the database
+    let stored = |name: &str| pseudonym::stored(pseudonymiser, name);
+
+    if config.aggregate_only {
+        for count in scan.population {
+            if let Some(server) = storage.get_server_by_addr(&count.server.to_string())? {
+                storage.insert_population_sample(&sql::PopulationSample {
+                    sample_id: 0,
+                    server_id: server.server_id,
+                    players: count.players as i32,
+                    bots: count.bots as i32,
+                    players_joined: count.joined as i32,
+                    players_left: count.left as i32,
+                    created_at: Utc::now(),
+                })?;
+                event_count += 1;
+            }
+        }
+    } else {
+        for server_players in scan.players.iter() {
+            let players: Vec<_> = server_players.1.iter().filter(|player| !scan.opt_out.contains(&player.name)).map(|player| sql::Player { player_id: 0, name: stored(&player.name) }).collect();
+            storage.insert_players_batch(&players, pseudonymiser.is_some())?;
+        }
+    }
+
+    for player_events in scan.player_events.iter() {
+        match storage.get_server_by_addr(&player_events.0.to_string())? {
+            Some(server) => {
+                for event in player_events.1 {   
+                    match event {
+                        PlayerEvent::PlayerJoined(player) => {
+                            storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { 
+                                event_id: 0,  
+                                server_id: server.server_id, 
+                                player_id: 0,
+                                kind: sql::PlayerEventKind::Join,
+                                created_at: Utc::now(),
+                            })?;
+                        },
+                        PlayerEvent::PlayerLeft(player) => {
+                            storage.insert_session(&stored(&player.name), &sql::Session { 
+                                session_id: 0, 
+                                server_id: server.server_id, 
+                                player_id: 0, 
+                                score: player.score, 
+                                duration: player.duration as f64, 
+                                joined_at: Utc::now() - Duration::from_secs(player.duration as u64), 
+                                left_at: Utc::now() })?;
+                            
+                            if let Some(score) = timeline.finish(server.server_id, &player.name) {
+                                storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { event_id: 0, server_id: server.server_id, player_id: 0, kind: sql::PlayerEventKind::PointChange { score }, created_at: Utc::now() })?;
+                                event_count += 1;
+                            }
+
+                            storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { 
+                                event_id: 0,  
+                                server_id: server.server_id, 
+                                player_id: 0, 
+                                kind: sql::PlayerEventKind::Leave,
+                                created_at: Utc::now(), })?;
+
+                        },
+                        PlayerEvent::TargetJoined(player) => {
+                            storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { 
+                                event_id: 0,  
+                                server_id: server.server_id, 
+                                player_id: 0, 
+                                kind: sql::PlayerEventKind::TargetJoin,
+                                created_at: Utc::now(), })?;
+
+                        },
+                        PlayerEvent::TargetLeft(player) => {
+                            storage.insert_session(&stored(&player.name), &sql::Session { 
+                                session_id: 0, 
+                                server_id: server.server_id, 
+                                player_id: 0, 
+                                score: player.score, 
+                                duration: player.duration as f64, 
+                                joined_at: Utc::now() - Duration::from_secs(player.duration as u64), 
+                                left_at: Utc::now() })?;
+
+                            if let Some(score) = timeline.finish(server.server_id, &player.name) {
+                                storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { event_id: 0, server_id: server.server_id, player_id: 0, kind: sql::PlayerEventKind::PointChange { score }, created_at: Utc::now() })?;
+                                event_count += 1;
+                            }
+
+                            storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { 
+                                event_id: 0,  
+                                server_id: server.server_id, 
+                                player_id: 0, 
+                                kind: sql::PlayerEventKind::TargetLeave,
+                                created_at: Utc::now(), })?;
+
+                        },
+                        PlayerEvent::PointUpdate(player, total) => {
+                            //Changes within the sampling interval are only kept in memory
+                            let score = match timeline.sample(server.server_id, &player.name, *total as i64) {
+                                Some(score) => score,
+                                None => continue,
+                            };
+                            storage.insert_player_event(&stored(&player.name), &sql::PlayerEvent { 
+                                event_id: 0, 
+                                server_id: server.server_id, 
+                                player_id: 0, 
+                                kind: sql::PlayerEventKind::PointChange { score },
+                                created_at: Utc::now() })?;
+
+                        }
+                    }
+                    event_count += 1;
+                }
+            },
+            None => continue,
+        }
+    }
+
+    Ok(event_count)
+}
+
+//File level maintenance, postgres has its own tools for these
+fn sqlite_only<'a>(storage: &'a mut dyn storage::Storage, command: &str) -> &'a mut rusqlite::Connection {
+    match storage.sqlite() {
+        Some(connection) => connection,
+        None => {eprintln!("db {} only works with the sqlite backend", command);exit(1)},
+    }
+}
+
+//Scans until stopped, only returns when the scanner can't be set up
+fn run(args: &Arguments, config: &Config, db_file: &str, target_file: &str, live: &status::LiveState) -> Result<(), String> {
 
     //connect to database specified in config
-    let mut connection = match Connection::open(db_file.clone()){
-        Ok(connection) => {println!("Opened database at ({})", db_file); connection},
-        Err(e) => {eprintln!("Failed to establish database connection ({:?})",e);exit(1)},
+    let backend = database_backend(config, db_file)?;
+    let mut storage = match backend.open() {
+        Ok(storage) => {info!("Opened database at ({})", backend); storage},
+        Err(e) => return Err(format!("Failed to establish database connection ({})", e)),
     };
+    match storage.migrate() {
+        Ok(0) => (),
+        Ok(applied) => info!("Applied ({}) database migrations", applied),
+        Err(e) => return Err(format!("Failed to migrate database ({})", e)),
+    }
+    if config.aggregate_only {
+        info!("Aggregate only, no player names or sessions will be stored");
+    }
 
-    let mut target_server_addresses: Vec<SocketAddr> = Vec::new();
+    let metrics = Arc::new(metrics::Metrics::new());
+    if config.metrics_enabled {
+        match metrics::serve(metrics.clone(), &config.metrics_address) {
+            Ok(_) => info!("Serving metrics at (http://{}/metrics)", config.metrics_address),
+            Err(e) => return Err(format!("Failed to start metrics server ({})", e)),
+        }
+    }
 
-    //Allocate space for running memory
-    let saved_info: Arc<RwLock<HashMap<SocketAddr, Info>>> = Arc::new(RwLock::new(HashMap::new()));
-    let saved_players = Arc::new(RwLock::new(HashMap::new()));
+    let target_server_addresses = live.servers.clone();
+
+    //Allocate space for running memory, the parts shown live are shared through LiveState
+    let saved_info = live.info.clone();
+    let saved_players = live.players.clone();
+    let saved_states = live.states.clone();
+    let broadcaster = live.broadcaster.clone();
     let saved_player_events_by_server = Arc::new(RwLock::new(HashMap::new()));
     let saved_server_events: Arc<RwLock<HashMap<SocketAddr, Vec<ServerEvent>>>> = Arc::new(RwLock::new(HashMap::new()));
     let mut saved_target_players : Vec<String> = Vec::new();
-    let mut custom_server_addresses : Vec<SocketAddr> = try_read_lines(&args.server_file.unwrap_or(config.server_file.clone()))
-        .expect("Failed to read target server file").iter()
-        .filter_map(|address| address.parse().ok())
-        .collect();
 
-    target_server_addresses.append(&mut custom_server_addresses);
+    if config.status_enabled {
+        let page = status::StatusPage {
+            live: live.clone(),
+            refresh_seconds: config.refresh_delay.max(5),
+        };
+        match status::serve(page, &config.status_address) {
+            Ok(_) => info!("Serving status page at (http://{}/)", config.status_address),
+            Err(e) => return Err(format!("Failed to start status page ({})", e)),
+        }
+    }
+
+    let pseudonymiser = config.pseudonym_secret.as_deref().map(pseudonym::Pseudonymiser::new);
+    if pseudonymiser.is_some() {
+        info!("Storing player names as pseudonyms");
+    }
+
+    let heartbeat_url = heartbeat::heartbeat_url(&config.heartbeat_url);
+
+    let mut timeline = scores::Timeline::from_config(config)?;
+    if !config.aggregate_only {
+        info!("{}", timeline.describe());
+    }
+
+    //Pruning runs on its own connection, the lock keeps it out of a scan's writes so a player is never deleted between its insert and its events
+    let db_lock = Arc::new(Mutex::new(()));
+    match retention::Policy::from_config(config) {
+        Ok(policy) if policy.is_empty() => (),
+        Ok(policy) => {
+            info!("Pruning ({}) tables every {}", policy.windows.len(), config.retention_interval);
+            retention::spawn(backend.clone(), policy, db_lock.clone(), metrics.clone());
+        },
+        Err(e) => return Err(e),
+    }
 
     //Allocate thread pool
     let pool = ThreadPoolBuilder::new().num_threads(200).build().unwrap();
+    let mut previous_total_players: Option<usize> = None;
 
     loop {
         //Load Targets and save to check for updated file.
-        match try_read_lines(&args.target_file.clone().unwrap_or(config.target_file.clone())){
-            Some(e) => {
-                if saved_target_players != e {
-                    saved_target_players = e;
-                    println!("Loaded ({}) target players", saved_target_players.len());
-                }
-            },
-            None => {},
+        if let Some(e) = try_read_lines(target_file) {
+            if saved_target_players != e {
+                saved_target_players = e;
+                info!("Loaded ({}) target players", saved_target_players.len());
+            }
         }
+        //Opt outs are kept when the file can't be read, so a bad edit doesn't start logging them again
+        if let Some(e) = config.opt_out_file.as_deref().and_then(try_read_lines) {
+            let mut opt_out_write = live.opt_out.write().unwrap();
+            if *opt_out_write != e {
+                *opt_out_write = e;
+                info!("Loaded ({}) opted out players", opt_out_write.len());
+            }
+        }
+        let opt_out = live.opt_out.read().unwrap().clone();
 
         let time_scan = Instant::now();
 
         let sucessful = AtomicUsize::new(0);
         let failed = AtomicUsize::new(0);
         let num_players = AtomicUsize::new(0);
+        let population_counts: RwLock<Vec<PopulationCount>> = RwLock::new(Vec::new());
         
         pool.install(|| {
             target_server_addresses.par_iter().for_each(|server| {
 
-                let mut a2s_client = A2SClient::new().expect("Failed to create A2S client");
-                a2s_client.max_size(3000);
+                let a2s_client = new_a2s_client();
 
                 let mut server_events: Vec<ServerEvent> = Vec::new();
                 let mut current_info : Option<Info> = None;
-                
-                match a2s_client.info(server) {
+
+                let time_query = Instant::now();
+                let info_result = a2s_client.info(server);
+                let latency_ms = time_query.elapsed().as_millis();
+                let was_up = {
+                    let mut saved_states_write = saved_states.write().unwrap();
+                    let previous = saved_states_write.get(server).cloned();
+                    saved_states_write.insert(*server, status::ServerState {
+                        up: info_result.is_ok(),
+                        latency_ms: info_result.as_ref().ok().map(|_| latency_ms),
+                        last_scan: Local::now(),
+                        last_seen: if info_result.is_ok() { Some(Local::now()) } else { previous.as_ref().and_then(|state| state.last_seen) },
+                        last_change: match &previous {
+                            Some(state) if state.up == info_result.is_ok() => state.last_change,
+                            _ => Some(Local::now()),
+                        },
+                    });
+                    previous.map(|state| state.up)
+                };
+
+                match info_result {
                     Ok(info) => {
+                        server_events.push(ServerEvent::ServerUp(server.to_string(), Box::new(info.clone()), latency_ms));
+
                         //Check if any server settings have changed
-                        let saved_info_read = saved_info.read().unwrap().clone();
-                        match saved_info_read.get(server){
-                            Some(previous) => {
-                                if info.name == previous.name && info.vac == previous.vac && info.visibility == previous.visibility && info.bots == previous.bots && info.map == previous.map && info.max_players == previous.max_players{
-                                }else {
-                                    server_events.push(ServerEvent::Settings(server.to_string(), info.clone()));
-                                }
-                            },
-                            None => {
-                                server_events.push(ServerEvent::Settings(server.to_string(), info.clone()));
-                            },
+                        let previous = saved_info.read().unwrap().get(server).cloned();
+                        let changed = match &previous {
+                            Some(previous) => info.map != previous.map || settings_changed(&info, previous),
+                            None => true,
                         };
+                        if changed {
+                            if previous.is_some() {
+                                if let Some(state) = saved_states.write().unwrap().get_mut(server) {
+                                    state.last_change = Some(Local::now());
+                                }
+                            }
+                            server_events.push(ServerEvent::Settings(server.to_string(), Box::new(info.clone()), previous.map(Box::new)));
+                        }
 
                         current_info = Some(info.clone());
+                        metrics.server_up.with_label_values(&[&server.to_string()]).set(1);
 
-                        server_events.push(ServerEvent::ServerUp(server.to_string()));
-                        
                         let mut saved_info_write = saved_info.write().unwrap();
                         saved_info_write.insert(*server, info.clone());
                     },
                     Err(error) => {
-                        server_events.push(ServerEvent::ServerDown(server.to_string()));
-                        eprintln!("{} : {} : {} : {}",Local::now().format("%H:%M:%S"), "Server Query Failed",server.to_string(), error);    
+                        server_events.push(ServerEvent::ServerDown(server.to_string(), error.to_string()));
+                        metrics.server_up.with_label_values(&[&server.to_string()]).set(0);
+                        warn!(server:% = server, event = "down"; "Server Query Failed ({})", error);
                     }
                 }
 
+                for event in &server_events {
+                    publish_server_event(&broadcaster, event, was_up);
+                }
+
                 {
                     let mut server_events_write = saved_server_events.write().unwrap();
                     server_events_write.insert(*server, server_events);
@@ -176,44 +738,65 @@ fn main() {
                     Ok(players) => {
                         let players = a2s_player_parse(&players);
                         let events = generate_player_events(&previous_players, &players, &saved_target_players);
+                        let joined = events.iter().filter(|event| matches!(event, PlayerEvent::PlayerJoined(_) | PlayerEvent::TargetJoined(_))).count();
+                        let left = events.iter().filter(|event| matches!(event, PlayerEvent::PlayerLeft(_) | PlayerEvent::TargetLeft(_))).count();
+
+                        //Opted out players still count towards the population, but nothing is logged, alerted or stored about them
+                        let events: Vec<PlayerEvent> = events.into_iter().filter(|event| !opt_out.contains(&event.player().name)).collect();
+
+                        //Monitor mode raises join/leave events from debug so they show at the default level
+                        let join_leave_level = match args.monitor {
+                            true => Level::Info,
+                            false => Level::Debug,
+                        };
 
                         for event in &events{
                             match event {
-                                PlayerEvent::PlayerJoined(player) =>  if args.monitor {println!("{} : {} : {}", Local::now().format("%H:%M:%S"), "Player Joined", player.name)},
-                                PlayerEvent::PlayerLeft(player) => if args.monitor {println!("{} : {} : {} , Points: {}, Duration: {}", Local::now().format("%H:%M:%S"), "Player Left", player.name, player.score, format_duration(player.duration as usize))},
+                                PlayerEvent::PlayerJoined(player) if !config.aggregate_only => log!(join_leave_level, server:% = server, event = "join", player = player.name.as_str(); "Player Joined : {}", player.name),
+                                PlayerEvent::PlayerLeft(player) if !config.aggregate_only => log!(join_leave_level, server:% = server, event = "leave", player = player.name.as_str(); "Player Left : {} , Points: {}, Duration: {}", player.name, player.score, format_duration(player.duration as usize)),
+                                //Names stay out of the logs too when only aggregates are kept
+                                PlayerEvent::PlayerJoined(_) | PlayerEvent::PlayerLeft(_) => (),
                                 PlayerEvent::TargetJoined(player) => {
-                                    println!("{} : {} : {}", Local::now().format("%H:%M:%S"), "Target Joined", player.name);
-                                    if config.webhook_enabled {send_alert(config.webhook_url.clone(), config.webhook_image.clone(), format!("__**{}**__ Detected in server \n({} : {})", player.name, match current_info.clone() {
+                                    info!(server:% = server, event = "target join", player = player.name.as_str(); "Target Joined : {}", player.name);
+                                    broadcaster.publish("target_join", object! { server: server.to_string(), player: player.name.as_str() });
+                                    if config.webhook_enabled && !send_alert(config.webhook_url.clone(), config.webhook_image.clone(), format!("__**{}**__ Detected in server \n({} : {})", player.name, match current_info.clone() {
                                         Some(info) => format!("{} : {}", info.name, info.map),
                                         None => "Unknown name : Unknown map".to_string(),
-                                    }, server.to_string()),"🚨🚨🚨 Alert.".to_string(), 16711680)};
+                                    }, server),"🚨🚨🚨 Alert.".to_string(), 16711680) {metrics.webhook_failures.inc()};
                                 },
                                 PlayerEvent::TargetLeft(player) => {
-                                    println!("{} : {} : {} : time: {}", Local::now().format("%H:%M:%S"), "Target Left", player.name, format_duration(player.duration as usize));
-                                    if config.webhook_enabled {send_alert(config.webhook_url.clone(), config.webhook_image.clone(), format!("__**{}**__ Left the server \n({} : {})\nPoints: {}, Duration: {}", player.name, match current_info.clone() {
+                                    info!(server:% = server, event = "target leave", player = player.name.as_str(); "Target Left : {} : time: {}", player.name, format_duration(player.duration as usize));
+                                    broadcaster.publish("target_leave", object! { server: server.to_string(), player: player.name.as_str(), score: player.score, duration: player.duration });
+                                    if config.webhook_enabled && !send_alert(config.webhook_url.clone(), config.webhook_image.clone(), format!("__**{}**__ Left the server \n({} : {})\nPoints: {}, Duration: {}", player.name, match current_info.clone() {
                                         Some(info) => format!("{} : {}", info.name, info.map),
                                         None => "Unknown name : Unknown map".to_string(),
-                                    }, server.to_string(), player.score, format_duration(player.duration as usize)), "🦀🦀🦀 Runner.".to_string(),22230)}
+                                    }, server, player.score, format_duration(player.duration as usize)), "🦀🦀🦀 Runner.".to_string(),22230) {metrics.webhook_failures.inc()}
                                 }
                                 PlayerEvent::PointUpdate(_player, _total) => {
                                     //do nothing
                                 }
                             };
                         }
-                        
+
+                        if joined > 0 || left > 0 {
+                            broadcaster.publish("server_population", object! { server: server.to_string(), players: players.len(), previous: previous_players.len(), joined: joined, left: left });
+                        }
+
+                        population_counts.write().unwrap().push(PopulationCount { server: *server, players: players.len(), bots: current_info.as_ref().map(|info| info.bots).unwrap_or(0), joined, left });
                         sucessful.fetch_add(1, Ordering::Relaxed);
                         num_players.fetch_add(players.len(), Ordering::Relaxed);
+                        metrics.server_players.with_label_values(&[&server.to_string()]).set(players.len() as i64);
                         {
                             let mut saved_players_write = saved_players.write().unwrap();
                             saved_players_write.insert(*server, players);
                         }
-                        {
+                        if !config.aggregate_only {
                             let mut saved_events_write = saved_player_events_by_server.write().unwrap();
                             saved_events_write.insert(*server, events);
                         }
                     },
                     Err(error) => {
-                        eprintln!("{} : {} : {} : {}",Local::now().format("%H:%M:%S"), "Player Query Failed",server.to_string(), error);
+                        warn!(server:% = server; "Player Query Failed ({})", error);
                         failed.fetch_add(1, Ordering::Relaxed);
                     },
                 }
@@ -221,170 +804,125 @@ fn main() {
         });
 
         let scan_time = time_scan.elapsed().as_millis();
-        let db_time = Instant::now();
-        let mut event_count = 0;
 
-        for address in target_server_addresses.clone(){
-            sql::insert_server(&connection, &sql::Server { server_id: 0, address: address.to_string() }).unwrap();
-            event_count += 1;
+        let total_players = num_players.load(Ordering::Relaxed);
+        if let Some(previous) = previous_total_players.filter(|previous| *previous != total_players) {
+            broadcaster.publish("population", object! {
+                players: total_players, previous: previous, servers_ok: sucessful.load(Ordering::Relaxed), servers_failed: failed.load(Ordering::Relaxed),
+            });
         }
-
-        for server_events in saved_server_events.read().unwrap().iter(){
-            let server_id = sql::get_server_by_addr(&connection, server_events.0.to_string()).unwrap();
-            for event in server_events.1{
-                match event {
-                    ServerEvent::ServerUp(_address) => {
-                        sql::insert_server_event(&connection, &sql::ServerEvent { event_id: 0, server_id: server_id.server_id, event_type: "up".to_string(), event_data: "".to_string(), created_at: Local::now().naive_local() }).unwrap();
-                    },
-                    ServerEvent::ServerDown(_address) => {
-                        sql::insert_server_event(&connection, &sql::ServerEvent { event_id: 0, server_id: server_id.server_id, event_type: "down".to_string(), event_data: "".to_string(), created_at: Local::now().naive_local() }).unwrap();
-                    },
-                    ServerEvent::Settings(_address, info) => {
-
-                        let mut new_settings = sql::ServerSettings { 
-                            setting_id: 0, 
-                            server_id: server_id.server_id, 
-                            name: info.name.to_string(), 
-                            max_players: info.max_players as i32, 
-                            current_map: info.map.to_string(), 
-                            vac_status: info.vac, 
-                            has_password: info.visibility, 
-                            game_version: info.version.to_string(), 
-                            bots: info.bots,
-                            created_at: NaiveDateTime::from_timestamp_opt(1, 0).unwrap() //temporary unix time 1;
-                        };
-
-                        //Read from the database to check if settings have changed or just been dropped from memory (program restart)
-                        match sql::get_server_settings(&connection, server_id.server_id){
-                            Ok(mut previous_settings) => {
-                                previous_settings.created_at = NaiveDateTime::from_timestamp_opt(1, 0).unwrap();
-                                previous_settings.setting_id = 0;
-                                if previous_settings != new_settings {
-                                    new_settings.created_at = Local::now().naive_local();
-                                    sql::insert_server_event(&connection, &sql::ServerEvent { event_id: 0, server_id: server_id.server_id, event_type: "setting change".to_string(), event_data: info.map.to_string(), created_at: Local::now().naive_local() }).unwrap();
-                                    sql::insert_server_settings(&connection, &new_settings).unwrap();
-                                    event_count += 1;
-                                }
-                            },
-                            Err(_) => {
-                                new_settings.created_at = Local::now().naive_local();
-                                sql::insert_server_event(&connection, &sql::ServerEvent { event_id: 0, server_id: server_id.server_id, event_type: "setting change".to_string(), event_data: info.map.to_string(), created_at: Local::now().naive_local() }).unwrap();
-                                sql::insert_server_settings(&connection, &new_settings).unwrap();
-                                event_count += 1;
-                            },
-                        }
-                    },
+        previous_total_players = Some(total_players);
+        metrics.scan_duration.observe(time_scan.elapsed().as_secs_f64());
+        metrics.players.set(num_players.load(Ordering::Relaxed) as i64);
+        metrics.servers_ok.set(sucessful.load(Ordering::Relaxed) as i64);
+        metrics.servers_failed.set(failed.load(Ordering::Relaxed) as i64);
+        let db_guard = db_lock.lock().unwrap();
+        let db_time = Instant::now();
+        let writes = ScanWrites {
+            servers: &target_server_addresses,
+            server_events: &saved_server_events.read().unwrap(),
+            population: &population_counts.read().unwrap(),
+            players: &saved_players.read().unwrap(),
+            player_events: &saved_player_events_by_server.read().unwrap(),
+            opt_out: &opt_out,
+        };
+        //A failed write drops the scan and opens a new connection, the next scan writes as usual once the database is back
+        let event_count = match write_scan(storage.as_mut(), &writes, config, pseudonymiser.as_ref(), &mut timeline) {
+            Ok(event_count) => event_count,
+            Err(e) => {
+                error!("Failed to write scan to database, dropping it ({})", e);
+                match backend.open() {
+                    Ok(reopened) => storage = reopened,
+                    Err(e) => error!("Failed to reconnect to database ({})", e),
                 }
-                event_count += 1;
+                0
             }
-        }
+        };
 
-        for server_players in saved_players.read().unwrap().iter(){
-            let players: Vec<_> = server_players.1.iter().map(|player| sql::Player { player_id: 0, name: player.name.clone() }).collect();
-            let _ = sql::insert_players_batch(&mut connection, &players).unwrap();
-        }
+        drop(db_guard);
+        metrics.db_duration.observe(db_time.elapsed().as_secs_f64());
+        metrics.events_written.inc_by(event_count);
 
-        for player_events in saved_player_events_by_server.read().unwrap().iter(){
-            match sql::get_server_by_addr(&connection, player_events.0.to_string()){
-                Ok(server) => {
-                    for event in player_events.1 {   
-                        match event {
-                            PlayerEvent::PlayerJoined(player) => {
-                                sql::insert_player_event(&connection, &player.name, &sql::PlayerEvent { 
-                                    event_id: 0,  
-                                    server_id: server.server_id, 
-                                    player_id: 0,
-                                    event_type: "join".to_string(), 
-                                    created_at: Local::now().naive_local(),
-                                    event_data: "".to_string(), 
-                                }).unwrap();
-                            },
-                            PlayerEvent::PlayerLeft(player) => {
-                                sql::insert_session(&connection, &player.name,&sql::Session { 
-                                    session_id: 0, 
-                                    server_id: server.server_id, 
-                                    player_id: 0, 
-                                    score: player.score, 
-                                    duration: player.duration as f64, 
-                                    joined_at: (Local::now() - Duration::from_secs(player.duration as u64)).naive_local(), 
-                                    left_at: Local::now().naive_local() }).unwrap();
-                                
-                                sql::insert_player_event(&connection, &player.name, &sql::PlayerEvent { 
-                                    event_id: 0,  
-                                    server_id: server.server_id, 
-                                    player_id: 0, 
-                                    event_type: "leave".to_string(), 
-                                    created_at: Local::now().naive_local(),
-                                    event_data: "".to_string(), }).unwrap();
-
-                            },
-                            PlayerEvent::TargetJoined(player) => {
-                                sql::insert_player_event(&connection,&player.name, &sql::PlayerEvent { 
-                                    event_id: 0,  
-                                    server_id: server.server_id, 
-                                    player_id: 0, 
-                                    event_type: "target join".to_string(), 
-                                    created_at: Local::now().naive_local(),
-                                    event_data: "".to_string(), }).unwrap();
-
-                            },
-                            PlayerEvent::TargetLeft(player) => {
-                                sql::insert_session(&connection, &player.name,&sql::Session { 
-                                    session_id: 0, 
-                                    server_id: server.server_id, 
-                                    player_id: 0, 
-                                    score: player.score, 
-                                    duration: player.duration as f64, 
-                                    joined_at: (Local::now() - Duration::from_secs(player.duration as u64)).naive_local(), 
-                                    left_at: Local::now().naive_local() }).unwrap();
-
-                                sql::insert_player_event(&connection, &player.name, &sql::PlayerEvent { 
-                                    event_id: 0,  
-                                    server_id: server.server_id, 
-                                    player_id: 0, 
-                                    event_type: "target leave".to_string(), 
-                                    created_at: Local::now().naive_local(),
-                                    event_data: "".to_string(), }).unwrap();
-
-                            },
-                            PlayerEvent::PointUpdate(player, total) => {
-                                sql::insert_player_event(&connection, &player.name, &sql::PlayerEvent { 
-                                    event_id: 0, 
-                                    server_id: server.server_id, 
-                                    player_id: 0, 
-                                    event_type: "point change".to_string(), 
-                                    event_data: total.to_string(), 
-                                    created_at: Local::now().naive_local() }).unwrap();
+        info!(
+            servers = target_server_addresses.len(), servers_ok = sucessful.load(Ordering::Relaxed), servers_failed = failed.load(Ordering::Relaxed),
+            events = event_count, players = num_players.load(Ordering::Relaxed), scan_ms = scan_time as u64, db_ms = db_time.elapsed().as_millis() as u64;
+            "Scanned ({}:{}:{}) : Events({}) Players({}) scan({}ms) db({}ms)", target_server_addresses.len(), sucessful.load(Ordering::Relaxed), failed.load(Ordering::Relaxed), event_count, num_players.load(Ordering::Relaxed), scan_time, db_time.elapsed().as_millis()
+        );
 
-                            }
-                        }
-                        event_count += 1;
-                    }
-                },
-                Err(_) => continue,
-            }                
+        {
+            let mut health = live.health.write().unwrap();
+            let scans = health.as_ref().map(|health| health.scans).unwrap_or(0) + 1;
+            *health = Some(status::ScanHealth {
+                finished: Local::now(),
+                scans,
+                scan_ms: scan_time,
+                db_ms: db_time.elapsed().as_millis(),
+                servers_ok: sucessful.load(Ordering::Relaxed),
+                servers_failed: failed.load(Ordering::Relaxed),
+                players: num_players.load(Ordering::Relaxed),
+                events: event_count,
+            });
         }
 
-        println!("{} : {} : Events({}) Players({}) scan({}ms) db({}ms)",Local::now().format("%H:%M:%S"), format!("Scanned ({}:{}:{})", target_server_addresses.len(), sucessful.fetch_or(0, Ordering::Relaxed), failed.fetch_or(0, Ordering::Relaxed)), event_count, num_players.fetch_or(0, Ordering::Relaxed), scan_time, db_time.elapsed().as_millis());
+        if config.heartbeat_enabled {
+            let summary = heartbeat::ScanSummary {
+                scan_ms: scan_time,
+                servers_ok: sucessful.load(Ordering::Relaxed),
+                servers_failed: failed.load(Ordering::Relaxed),
+                players: num_players.load(Ordering::Relaxed),
+                events: event_count,
+            };
 
-        let ping_param: String = match UPTIMEKUMA_PING {
-            true => scan_time.to_string(),
-            false => "".to_string(),
-        };
-        if config.heartbeat_enabled {send_heartbeat(config.heartbeat_url.clone() + &ping_param)}
+            //Too many failed servers pings the down url instead, or skips the heartbeat if there is none
+            let url = match config.heartbeat_failure_threshold {
+                threshold if threshold > 0 && summary.servers_failed >= threshold => config.heartbeat_down_url.as_ref(),
+                _ => Some(&heartbeat_url),
+            };
+            if let Some(url) = url {
+                if !heartbeat::send_heartbeat(url, config.heartbeat_method, config.heartbeat_body.as_deref(), &summary) {metrics.heartbeat_failures.inc()}
+            }
+        }
 
         sleep(Duration::from_secs(config.refresh_delay));
-    };
+    }
+}
+
+//Settings other than the map, which is streamed as its own event
+fn settings_changed(info: &Info, previous: &Info) -> bool {
+    !(info.name == previous.name && info.vac == previous.vac && info.visibility == previous.visibility && info.bots == previous.bots && info.max_players == previous.max_players)
 }
 
-fn generate_player_events(previous_players : &Vec<Player>, current_players : &Vec<Player>, target_players: &Vec<String>) -> Vec<PlayerEvent>{
+//Only transitions are streamed, the database still gets every up/down scan
+fn publish_server_event(broadcaster: &events::Broadcaster, event: &ServerEvent, was_up: Option<bool>) {
+    match event {
+        ServerEvent::ServerUp(address, info, latency_ms) if was_up != Some(true) => {
+            broadcaster.publish("server_up", object! { server: address.as_str(), name: info.name.as_str(), map: info.map.as_str(), latency_ms: *latency_ms as u64 })
+        },
+        ServerEvent::ServerDown(address, error) if was_up != Some(false) => {
+            broadcaster.publish("server_down", object! { server: address.as_str(), error: error.as_str() })
+        },
+        ServerEvent::Settings(address, info, Some(previous)) => {
+            if info.map != previous.map {
+                broadcaster.publish("map_change", object! { server: address.as_str(), map: info.map.as_str(), previous_map: previous.map.as_str() });
+            }
+            if settings_changed(info, previous) {
+                broadcaster.publish("settings_change", object! {
+                    server: address.as_str(), name: info.name.as_str(), max_players: info.max_players, bots: info.bots, has_password: info.visibility, vac_status: info.vac,
+                });
+            }
+        },
+        _ => (),
+    }
+}
+
+fn generate_player_events(previous_players : &[Player], current_players : &[Player], target_players: &[String]) -> Vec<PlayerEvent>{
     let mut events : Vec<PlayerEvent> = Vec::new();
 
     let previous_names : Vec<String> = previous_players.iter().map(|player| player.name.clone()).collect();
     let current_names : Vec<String> = current_players.iter().map(|player| player.name.clone()).collect();
 
     for player in current_players {
-        if !previous_names.contains(&&player.name) && !player.name.is_empty() {
+        if !previous_names.contains(&player.name) && !player.name.is_empty() {
             if target_players.contains(&player.name) {
                 events.push(PlayerEvent::TargetJoined(player.clone()));
             } else {
@@ -392,17 +930,15 @@ fn generate_player_events(previous_players : &Vec<Player>, current_players : &Ve
             }
         }else if !player.name.is_empty() {
             for prev_player in previous_players{
-                if player.name == prev_player.name{
-                    if player.score != prev_player.score{
-                        events.push(PlayerEvent::PointUpdate(player.clone(), player.score as usize))
-                    }
+                if player.name == prev_player.name && player.score != prev_player.score {
+                    events.push(PlayerEvent::PointUpdate(player.clone(), player.score as usize))
                 }
             }
         }
     }
 
     for player in previous_players {
-        if !current_names.contains(&&player.name) & !player.name.is_empty() {
+        if !current_names.contains(&player.name) & !player.name.is_empty() {
             if target_players.contains(&player.name){
                 events.push(PlayerEvent::TargetLeft(player.clone()));
             }else {
@@ -410,10 +946,10 @@ fn generate_player_events(previous_players : &Vec<Player>, current_players : &Ve
             }      
         }
     }
-    return events;
+    events
 }
 
-fn send_alert(url: String, image: String, input_string: String, title: String, color: u64) {
+fn send_alert(url: String, image: String, input_string: String, title: String, color: u64) -> bool {
         let json_request = object! {
         username: "TF2-Alert",
         avatar_url: image,
@@ -429,20 +965,27 @@ fn send_alert(url: String, image: String, input_string: String, title: String, c
 
     let json = json_request.dump();
 
-    ureq::post(&url)
+    let call = ureq::post(&url)
         .set("Content-Type", "application/json")
-        .send(json.as_bytes())
-        .expect("Failed to post to webhook");
-}
-
-fn send_heartbeat(url: String) {
-    let call = ureq::get(&url).call();
+        .send(json.as_bytes());
     match call {
-        Ok(_) => (),
-        Err(e) => eprintln!("Failed to send heartbeat ({})", e),
+        Ok(_) => true,
+        Err(e) => {error!("Failed to post to webhook ({})", e); false},
     }
 }
 
+fn load_servers(server_file: &str) -> Vec<SocketAddr> {
+    try_read_lines(server_file)
+        .expect("Failed to read target server file").iter()
+        .filter_map(|address| address.parse().ok())
+        .collect()
+}
+
+//Where the scanner and the db commands read and write
+fn database_backend(config: &Config, db_file: &str) -> Result<storage::Backend, String> {
+    storage::Backend::new(config.database_backend, db_file, config.postgres_url.as_deref(), config.legacy_time_zone.as_deref())
+}
+
 fn try_read_lines(filename: &str) -> Option<Vec<String>> {
     match read_to_string(filename) {
         Ok(data) => Some(data.lines().map(String::from).collect()),
@@ -457,6 +1000,12 @@ fn format_duration(input: usize) -> String{
     format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
 }
 
+fn new_a2s_client() -> A2SClient {
+    let mut a2s_client = A2SClient::new().expect("Failed to create A2S client");
+    a2s_client.max_size(3000);
+    a2s_client
+}
+
 fn a2s_player_parse(input: &[a2s::players::Player]) -> Vec<Player> {
     input.iter().map(|player| Player {
         name: player.name.clone(),
@@ -465,7 +1014,27 @@ fn a2s_player_parse(input: &[a2s::players::Player]) -> Vec<Player> {
     }).collect()
 }
 
-fn load_config(path: String) -> Config {
-    let contents = fs::read_to_string(path).expect("Failed to read configuration file");
-    toml::from_str(&contents).expect("Failed to parse configuration file")
+fn default_log_filter() -> String {
+    "info".to_string()
+}
+
+fn default_metrics_address() -> String {
+    "127.0.0.1:9184".to_string()
+}
+
+fn default_status_address() -> String {
+    "127.0.0.1:9185".to_string()
+}
+
+fn default_retention_interval() -> String {
+    "1h".to_string()
+}
+
+fn default_score_sample_interval() -> String {
+    "1m".to_string()
+}
+
+fn load_config(path: &str) -> Result<Config, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read configuration file ({}) ({})", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse configuration file ({}) ({})", path, e))
 }
@@ -0,0 +1,260 @@
+use crate::status::{LiveState, ServerState};
+use chrono::{DateTime, Local};
+use json::JsonValue;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Cell, List, ListItem, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use std::{collections::VecDeque, io, net::SocketAddr, sync::mpsc::Receiver, time::Duration};
+
+const COLUMNS: [&str; 7] = ["Status", "Server", "Name", "Map", "Players", "Latency", "Changed"];
+const MAX_EVENTS: usize = 500;
+
+//One server as shown in the table, copied out of the live state each frame
+struct ServerRow {
+    address: SocketAddr,
+    state: Option<ServerState>,
+    name: String,
+    map: String,
+    players: Option<usize>,
+    max_players: Option<u8>,
+}
+
+struct Dashboard<'a> {
+    live: &'a LiveState,
+    refresh_delay: u64,
+    events: Receiver<JsonValue>,
+    log: VecDeque<Line<'static>>,
+    sort: usize,
+    reverse: bool,
+    table: TableState,
+}
+
+pub fn run(live: &LiveState, refresh_delay: u64) -> io::Result<()> {
+    let mut dashboard = Dashboard {
+        live,
+        refresh_delay,
+        events: live.broadcaster.subscribe(),
+        log: VecDeque::new(),
+        sort: 1,
+        reverse: false,
+        table: TableState::default().with_selected(0),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = dashboard.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Dashboard<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            if self.live.stopped.read().unwrap().is_some() {
+                return Ok(());
+            }
+            while let Ok(event) = self.events.try_recv() {
+                self.log.push_front(event_line(&event));
+                self.log.truncate(MAX_EVENTS);
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            //Redraw at least every 250ms so ages and new scans show without a key press
+            if !event::poll(Duration::from_millis(250))? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Char('s') | KeyCode::Right | KeyCode::Tab => self.sort = (self.sort + 1) % COLUMNS.len(),
+                KeyCode::Char('S') | KeyCode::Left | KeyCode::BackTab => self.sort = (self.sort + COLUMNS.len() - 1) % COLUMNS.len(),
+                KeyCode::Char('r') => self.reverse = !self.reverse,
+                KeyCode::Char(digit @ '1'..='7') => self.sort = digit as usize - '1' as usize,
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::Char('c') => self.log.clear(),
+                _ => (),
+            }
+        }
+    }
+
+    fn rows(&self) -> Vec<ServerRow> {
+        let info = self.live.info.read().unwrap();
+        let players = self.live.players.read().unwrap();
+        let states = self.live.states.read().unwrap();
+
+        let mut rows: Vec<ServerRow> = self
+            .live
+            .servers
+            .iter()
+            .map(|address| {
+                let state = states.get(address).cloned();
+                let up = state.as_ref().map(|state| state.up).unwrap_or(false);
+                ServerRow {
+                    address: *address,
+                    name: info.get(address).map(|info| info.name.clone()).unwrap_or_default(),
+                    map: info.get(address).map(|info| info.map.clone()).unwrap_or_default(),
+                    //The scanner keeps the last player list of a server that went down
+                    players: players.get(address).filter(|_| up).map(Vec::len),
+                    max_players: info.get(address).map(|info| info.max_players),
+                    state,
+                }
+            })
+            .collect();
+
+        rows.sort_by(|a, b| {
+            let ordering = match self.sort {
+                0 => status_rank(a).cmp(&status_rank(b)),
+                1 => a.address.cmp(&b.address),
+                2 => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                3 => a.map.cmp(&b.map),
+                4 => b.players.cmp(&a.players),
+                5 => latency(a).cmp(&latency(b)),
+                _ => change(b).cmp(&change(a)),
+            };
+            let ordering = ordering.then_with(|| a.address.cmp(&b.address));
+            if self.reverse { ordering.reverse() } else { ordering }
+        });
+        rows
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, events_area, footer_area] = Layout::vertical([Constraint::Min(6), Constraint::Percentage(35), Constraint::Length(2)]).areas(frame.area());
+        let now = Local::now();
+        let rows = self.rows();
+
+        let up = rows.iter().filter(|row| row.state.as_ref().map(|state| state.up).unwrap_or(false)).count();
+        let header = Row::new(COLUMNS.iter().enumerate().map(|(index, column)| {
+            let arrow = match (index == self.sort, self.reverse) {
+                (true, false) => " ▼",
+                (true, true) => " ▲",
+                _ => "",
+            };
+            Cell::from(format!("{}{}", column, arrow))
+        }))
+        .style(Style::new().bold().underlined());
+
+        let table_rows = rows.iter().map(|row| {
+            let (status, colour) = match &row.state {
+                Some(state) if state.up => ("up", Color::Green),
+                Some(_) => ("down", Color::Red),
+                None => ("pending", Color::DarkGray),
+            };
+            let players = match (row.players, row.max_players) {
+                (Some(players), Some(max)) => format!("{:>3}/{}", players, max),
+                (None, Some(max)) => format!("  ?/{}", max),
+                _ => String::new(),
+            };
+            Row::new(vec![
+                Cell::from(status).style(Style::new().fg(colour).bold()),
+                Cell::from(row.address.to_string()),
+                Cell::from(row.name.clone()),
+                Cell::from(row.map.clone()),
+                Cell::from(players),
+                Cell::from(row.state.as_ref().and_then(|state| state.latency_ms).map(|latency| format!("{}ms", latency)).unwrap_or_default()),
+                Cell::from(row.state.as_ref().and_then(|state| state.last_change).map(|time| ago(now, time)).unwrap_or_default()),
+            ])
+        });
+
+        let widths = [
+            Constraint::Length(7),
+            Constraint::Length(21),
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(10),
+        ];
+        let table = Table::new(table_rows, widths)
+            .header(header)
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .block(Block::bordered().title(format!(" Servers {}/{} up ", up, rows.len())));
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+
+        let events: Vec<ListItem> = self.log.iter().map(|line| ListItem::new(line.clone())).collect();
+        frame.render_widget(List::new(events).block(Block::bordered().title(" Events ")), events_area);
+
+        let health = match self.live.health.read().unwrap().clone() {
+            Some(health) => {
+                let next = self.refresh_delay as i64 - (now - health.finished).num_seconds();
+                Line::from(vec![
+                    Span::raw(format!("Scan #{} at {} ", health.scans, health.finished.format("%H:%M:%S"))),
+                    Span::styled(format!("{} ok ", health.servers_ok), Style::new().fg(Color::Green)),
+                    Span::styled(format!("{} failed ", health.servers_failed), Style::new().fg(if health.servers_failed > 0 { Color::Red } else { Color::DarkGray })),
+                    Span::raw(format!(
+                        "| {} players | scan {}ms db {}ms | {} events | {}",
+                        health.players,
+                        health.scan_ms,
+                        health.db_ms,
+                        health.events,
+                        if next > 0 { format!("next scan in {}s", next) } else { "scanning".to_string() }
+                    )),
+                ])
+            }
+            None => Line::from("First scan in progress"),
+        };
+        let help = Line::from("q quit  s/←/→ sort  1-7 sort by column  r reverse  ↑/↓ select  c clear events").dark_gray();
+        frame.render_widget(Paragraph::new(vec![health, help]), footer_area);
+    }
+}
+
+fn status_rank(row: &ServerRow) -> u8 {
+    match &row.state {
+        Some(state) if !state.up => 0,
+        None => 1,
+        Some(_) => 2,
+    }
+}
+
+//Unknown latency sorts last
+fn latency(row: &ServerRow) -> u128 {
+    row.state.as_ref().and_then(|state| state.latency_ms).unwrap_or(u128::MAX)
+}
+
+fn change(row: &ServerRow) -> Option<DateTime<Local>> {
+    row.state.as_ref().and_then(|state| state.last_change)
+}
+
+fn ago(now: DateTime<Local>, time: DateTime<Local>) -> String {
+    let seconds = (now - time).num_seconds().max(0);
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn event_line(event: &JsonValue) -> Line<'static> {
+    let time = DateTime::parse_from_rfc3339(event["time"].as_str().unwrap_or_default())
+        .map(|time| time.with_timezone(&Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default();
+    let server = &event["server"];
+
+    let (label, colour, text) = match event["type"].as_str().unwrap_or_default() {
+        "server_up" => ("UP", Color::Green, format!("{} {} ({})", server, event["name"], event["map"])),
+        "server_down" => ("DOWN", Color::Red, format!("{} {}", server, event["error"])),
+        "map_change" => ("MAP", Color::Cyan, format!("{} {} -> {}", server, event["previous_map"], event["map"])),
+        "settings_change" => ("SETTINGS", Color::Yellow, format!("{} {} ({} max)", server, event["name"], event["max_players"])),
+        "target_join" => ("TARGET", Color::Magenta, format!("{} joined {}", event["player"], server)),
+        "target_leave" => ("TARGET", Color::Magenta, format!("{} left {}", event["player"], server)),
+        "server_population" => ("PLAYERS", Color::DarkGray, format!("{} {} players (+{} -{})", server, event["players"], event["joined"], event["left"])),
+        "population" => ("TOTAL", Color::DarkGray, format!("{} players (was {})", event["players"], event["previous"])),
+        other => ("EVENT", Color::White, format!("{} {}", other, event.dump())),
+    };
+
+    Line::from(vec![
+        Span::raw(format!("{} ", time)),
+        Span::styled(format!("{:<9}", label), Style::new().fg(colour).bold()),
+        Span::raw(text),
+    ])
+}